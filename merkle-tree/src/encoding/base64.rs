@@ -10,7 +10,7 @@ const ALPHABET: [char; 64] = [
 ];
 const PADDING: char = '=';
 
-struct Base64{
+pub struct Base64{
     non_instance: PhantomData<bool>,
 }
 
@@ -20,7 +20,7 @@ impl Base64{
     /// This should index in the alphabet
     #[inline(always)]
     fn first(fo: u8, _so:u8, _to: u8) -> u8{
-        fo | 0xFC
+        fo >> 2
     }
 
     /// Use the least significant 2 bits of the first octect and 
@@ -29,7 +29,7 @@ impl Base64{
     /// This should index in the alphabet
     #[inline(always)]
     fn second(fo: u8, so:u8, _to: u8) -> u8{
-        ((fo | 0x03) | (so | 0xF0)) >> 2
+        ((fo & 0x03) << 4) | (so >> 4)
     }
 
     /// Use the least significant 4 bits of the second octect and 
@@ -38,7 +38,7 @@ impl Base64{
     /// This should index in the alphabet
     #[inline(always)]
     fn third(_fo: u8, so:u8, to: u8) -> u8{
-        ((so | 0x0F) | (to | 0xC0)) >> 2
+        ((so & 0x0F) << 2) | (to >> 6)
     }
 
     /// Use the least significant 6 bits of the third octect
@@ -48,6 +48,19 @@ impl Base64{
     fn fourth(_fo: u8, _so:u8, to: u8) -> u8{
         to & 0x3F
     }
+
+    /// Position of the character in the alphabet
+    #[inline(always)]
+    fn index_of(c: u8) -> Option<u8>{
+        match c {
+            b'A'..=b'Z' => Some(c - b'A'),
+            b'a'..=b'z' => Some(c - b'a' + 26),
+            b'0'..=b'9' => Some(c - b'0' + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None
+        }
+    }
 }
 
 impl Digester for Base64{
@@ -57,7 +70,7 @@ impl Digester for Base64{
         // this could be a problem with big u8 vectors
         let mut s = String::with_capacity(bits.len() * 2);
         
-        for i in (0..(bits.len() / 3)).step_by(3) {
+        for i in (0..(bits.len() / 3) * 3).step_by(3) {
             s.push(ALPHABET[Self::first(bits[i], bits[i+1], bits[i+2]) as usize]);
             s.push(ALPHABET[Self::second(bits[i], bits[i+1], bits[i+2]) as usize]);
             s.push(ALPHABET[Self::third(bits[i], bits[i+1], bits[i+2]) as usize]);
@@ -86,4 +99,67 @@ impl Digester for Base64{
 
         s
     }
-}
\ No newline at end of file
+    fn undigest(text: &str) -> Option<Vec<u8>> {
+        let chars = text.as_bytes();
+        if !chars.len().is_multiple_of(4) {
+            return None;
+        }
+
+        let mut bits = Vec::with_capacity(chars.len() / 4 * 3);
+        for (i, quad) in chars.chunks(4).enumerate() {
+            let last = i == chars.len() / 4 - 1;
+            let padding = quad.iter().rev().take_while(|c| **c == PADDING as u8).count();
+            if padding > 2 || (padding > 0 && !last) {
+                return None;
+            }
+
+            // Padding is decoded as zeros and dropped afterwards
+            let mut sextets = [0u8; 4];
+            for (j, c) in quad[..4 - padding].iter().enumerate() {
+                sextets[j] = Self::index_of(*c)?;
+            }
+
+            let decoded = [
+                (sextets[0] << 2) | (sextets[1] >> 4),
+                (sextets[1] << 4) | (sextets[2] >> 2),
+                (sextets[2] << 6) | sextets[3],
+            ];
+            bits.extend_from_slice(&decoded[..3 - padding]);
+        }
+
+        Some(bits)
+    }
+}
+
+#[cfg(test)]
+mod test{
+
+    use super::*;
+
+    #[test]
+    fn base64_digester(){
+        let pairs = [
+            (b"".as_slice(), ""),
+            (b"f".as_slice(), "Zg=="),
+            (b"fo".as_slice(), "Zm8="),
+            (b"foo".as_slice(), "Zm9v"),
+            (b"foob".as_slice(), "Zm9vYg=="),
+            (b"fooba".as_slice(), "Zm9vYmE="),
+            (b"foobar".as_slice(), "Zm9vYmFy"),
+            (b"\xF0\x9F\x98\x81\xFF".as_slice(), "8J+Ygf8="),
+        ];
+
+        for pair in pairs{
+            assert_eq!(Base64::digest(pair.0), pair.1);
+            assert_eq!(Base64::undigest(pair.1).unwrap(), pair.0);
+        }
+    }
+
+    #[test]
+    fn base64_undigest_rejects(){
+        assert!(Base64::undigest("Zm9").is_none());
+        assert!(Base64::undigest("Zg==Zm9v").is_none());
+        assert!(Base64::undigest("Z===").is_none());
+        assert!(Base64::undigest("Zm9*").is_none());
+    }
+}
@@ -19,6 +19,22 @@ impl Digester for Hex{
 
         s
     }
+
+    fn undigest(text: &str) -> Option<Vec<u8>> {
+        if !text.len().is_multiple_of(2) {
+            return None;
+        }
+
+        let mut bits = Vec::with_capacity(text.len() / 2);
+        let chars = text.as_bytes();
+        for pair in chars.chunks(2){
+            let high = (pair[0] as char).to_digit(16)?;
+            let low = (pair[1] as char).to_digit(16)?;
+            bits.push(((high << 4) | low) as u8);
+        }
+
+        Some(bits)
+    }
 }
 
 #[cfg(test)]
@@ -39,4 +55,13 @@ mod test{
             assert_eq!(Hex::digest(pair.0), pair.1);
         }
     }
+
+    #[test]
+    fn hex_undigest(){
+        assert_eq!(Hex::undigest("5468697320697320612074657374").unwrap(), b"This is a test");
+        assert_eq!(Hex::undigest("f09f9881").unwrap(), b"\xF0\x9F\x98\x81");
+        assert_eq!(Hex::undigest("").unwrap(), b"");
+        assert!(Hex::undigest("F09").is_none());
+        assert!(Hex::undigest("G0").is_none());
+    }
 }
\ No newline at end of file
@@ -3,6 +3,9 @@ pub mod base64;
 
 pub trait Digester{
     fn digest(bits: &[u8]) -> String;
+
+    /// Inverse of `digest`, `None` if the text could not have been produced by it
+    fn undigest(text: &str) -> Option<Vec<u8>>;
 }
 
 pub trait Digestable{
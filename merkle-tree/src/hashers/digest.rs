@@ -0,0 +1,234 @@
+use std::{borrow::Cow, cmp::Ordering, fmt, hash::{Hash, Hasher}, hint::black_box, marker::PhantomData};
+
+use crate::{encoding::{Digestable, hex::Hex}, merkle::combiner::NodeCombiner};
+
+use super::{CryptoHash, CryptoHasher, Hashable};
+
+/// Hash of `N` bytes given by `H`, held inline and copied instead of allocated.
+///
+/// Hashes of different hashers are different types, so they can't be compared
+/// or mixed in the same tree by mistake.
+pub struct Digest<H, const N: usize>{
+    bytes: [u8; N],
+    hasher: PhantomData<fn() -> H>,
+}
+
+impl<H, const N: usize> Digest<H, N> {
+    pub fn new(bytes: [u8; N]) -> Self{
+        Self { bytes, hasher: PhantomData }
+    }
+
+    /// `None` if `bytes` is not `N` bytes long
+    pub fn from_slice(bytes: &[u8]) -> Option<Self>{
+        bytes.try_into().ok().map(Self::new)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; N]{
+        &self.bytes
+    }
+}
+
+impl<H, const N: usize> Clone for Digest<H, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<H, const N: usize> Copy for Digest<H, N> {}
+
+impl<H, const N: usize> PartialEq for Digest<H, N> {
+    fn eq(&self, other: &Self) -> bool {
+        let difference = self.bytes.iter().zip(&other.bytes).fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        black_box(difference) == 0
+    }
+}
+
+impl<H, const N: usize> Eq for Digest<H, N> {}
+
+impl<H, const N: usize> Ord for Digest<H, N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bytes.cmp(&other.bytes)
+    }
+}
+
+impl<H, const N: usize> PartialOrd for Digest<H, N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<H, const N: usize> Hash for Digest<H, N> {
+    fn hash<S: Hasher>(&self, state: &mut S) {
+        self.bytes.hash(state);
+    }
+}
+
+impl<H, const N: usize> fmt::Debug for Digest<H, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Digest({})", self.digest::<Hex>())
+    }
+}
+
+impl<H, const N: usize> AsRef<[u8]> for Digest<H, N> {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<H, const N: usize> Digestable for Digest<H, N> {
+    fn bits(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<H, const N: usize> Hashable for Digest<H, N> {
+    fn to_bits(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<H, const N: usize> From<Digest<H, N>> for CryptoHash {
+    fn from(digest: Digest<H, N>) -> Self {
+        CryptoHash { data: digest.bytes.to_vec() }
+    }
+}
+
+impl<H, const N: usize> TryFrom<&CryptoHash> for Digest<H, N> {
+    /// The length of the hash
+    type Error = usize;
+
+    fn try_from(hash: &CryptoHash) -> Result<Self, usize> {
+        Self::from_slice(&hash.data).ok_or(hash.data.len())
+    }
+}
+
+/// What the hashes of a hasher, and the nodes of a `MerkleTree`, are kept as.
+///
+/// Proofs are checked with `==`, so it has to compare in constant time.
+pub trait HashOutput: Clone + Eq + AsRef<[u8]> + Send + Sync{
+    /// `None` if `bytes` does not have the length of the output
+    fn from_slice(bytes: &[u8]) -> Option<Self>;
+
+    /// Panics if `hash` does not have the length of the output
+    fn from_hash(hash: CryptoHash) -> Self{
+        let len = hash.data.len();
+        Self::from_slice(&hash.data).unwrap_or_else(|| panic!("A hash of {} bytes does not fit the output", len))
+    }
+
+    /// The output as the `CryptoHash` proofs work with
+    fn as_hash(&self) -> Cow<'_, CryptoHash>{
+        Cow::Owned(CryptoHash { data: self.as_ref().to_vec() })
+    }
+
+    /// Hash of a leaf of a tree whose hasher is `H`
+    fn leaf<H: CryptoHasher>(bytes: &[u8]) -> Self;
+
+    /// Hash of the parent of `left` and `right`, the right one holding the lower indices
+    fn combine<H: CryptoHasher, C: NodeCombiner>(left: &Self, right: &Self) -> Self;
+}
+
+/// Copies the `Output` of the hasher of the tree, which is already a `Digest` of the same length
+impl<G, const N: usize> HashOutput for Digest<G, N> {
+    fn from_slice(bytes: &[u8]) -> Option<Self> {
+        Digest::from_slice(bytes)
+    }
+
+    fn leaf<H: CryptoHasher>(bytes: &[u8]) -> Self {
+        Self::from_output(H::digest_leaf(bytes))
+    }
+
+    fn combine<H: CryptoHasher, C: NodeCombiner>(left: &Self, right: &Self) -> Self {
+        Self::from_output(C::combine_digest::<H>(&left.bytes, &right.bytes))
+    }
+}
+
+impl<G, const N: usize> Digest<G, N> {
+    fn from_output(output: impl AsRef<[u8]>) -> Self{
+        Self::from_slice(output.as_ref()).expect("The hasher of the tree gives hashes of another length")
+    }
+}
+
+/// Any length goes, every hash is its own allocation
+impl HashOutput for CryptoHash {
+    fn from_slice(bytes: &[u8]) -> Option<Self> {
+        Some(CryptoHash { data: bytes.to_vec() })
+    }
+
+    fn from_hash(hash: CryptoHash) -> Self {
+        hash
+    }
+
+    fn as_hash(&self) -> Cow<'_, CryptoHash> {
+        Cow::Borrowed(self)
+    }
+
+    fn leaf<H: CryptoHasher>(bytes: &[u8]) -> Self {
+        H::hash_leaf(bytes)
+    }
+
+    fn combine<H: CryptoHasher, C: NodeCombiner>(left: &Self, right: &Self) -> Self {
+        C::combine::<H>(left, right)
+    }
+}
+
+impl AsRef<[u8]> for CryptoHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::{BTreeSet, HashSet};
+
+    use crate::hashers::{sha256::SHA256, keccak256::Keccak256, separated::{Separated, ByteTags}};
+
+    use super::*;
+
+    #[test]
+    fn copies_and_compares(){
+        let digest = SHA256::digest(b"hello");
+        let copy = digest;
+        assert_eq!(digest, copy);
+        assert_eq!(digest.as_ref(), SHA256::hash(b"hello").data.as_slice());
+        assert!(SHA256::digest(b"a") != SHA256::digest(b"b"));
+        // Only the last byte differs, every byte counts in the fold
+        assert_ne!(Digest::<SHA256, 2>::new([7, 1]), Digest::new([7, 2]));
+        // Same length, another hasher, so another type
+        let keccak: Digest<Keccak256, 32> = Keccak256::digest(b"hello");
+        assert_ne!(keccak.as_ref(), digest.as_ref());
+
+        let set: HashSet<Digest<SHA256, 32>> = [digest, copy, SHA256::digest(b"other")].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        let sorted: BTreeSet<Digest<SHA256, 2>> = [Digest::new([1, 0]), Digest::new([0, 9])].into_iter().collect();
+        assert_eq!(sorted.into_iter().next(), Some(Digest::new([0, 9])));
+        assert_eq!(format!("{:?}", Digest::<SHA256, 2>::new([0xAB, 1])), "Digest(AB01)");
+    }
+
+    #[test]
+    fn parts_hash_as_one(){
+        let whole: Vec<u8> = (0..300u32).map(|i| (i * 7) as u8).collect();
+        for split in [0, 1, 55, 56, 64, 135, 136, 137, 200, 300] {
+            let (first, second) = whole.split_at(split);
+            assert_eq!(SHA256::digest_parts(&[first, second]), SHA256::digest(&whole), "{}", split);
+            assert_eq!(Keccak256::digest_parts(&[first, &[], second]), Keccak256::digest(&whole), "{}", split);
+            assert_eq!(SHA256::digest(&whole).as_ref(), SHA256::hash(&whole).data.as_slice());
+            assert_eq!(Keccak256::digest(&whole).as_ref(), Keccak256::hash(&whole).data.as_slice());
+        }
+
+        type Tagged = Separated<SHA256, ByteTags>;
+        assert_eq!(Tagged::digest_leaf(b"leaf").as_ref(), Tagged::hash_leaf(b"leaf").data.as_slice());
+        assert_eq!(Tagged::digest_node(b"left", b"right").as_ref(), Tagged::hash_node(b"leftright").data.as_slice());
+    }
+
+    #[test]
+    fn converts_from_and_to_hashes(){
+        let hash = SHA256::hash(b"hello");
+        let digest = Digest::<SHA256, 32>::try_from(&hash).unwrap();
+        assert_eq!(CryptoHash::from(digest).data, hash.data);
+        assert_eq!(Digest::<SHA256, 20>::try_from(&hash), Err(32));
+        assert_eq!(Digest::<SHA256, 4>::from_slice(&[1, 2, 3]), None);
+        assert_eq!(digest.as_hash().data, hash.data);
+        assert!(std::panic::catch_unwind(|| Digest::<SHA256, 20>::from_hash(SHA256::hash(b"hello"))).is_err());
+    }
+}
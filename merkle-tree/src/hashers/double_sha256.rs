@@ -0,0 +1,33 @@
+use std::marker::PhantomData;
+
+use super::{CryptoHash, CryptoHasher, sha256::SHA256, digest::Digest};
+
+/// `SHA256` applied twice, the hash Bitcoin uses for transactions, blocks and their Merkle trees
+pub struct DoubleSHA256 {
+    non_instance: PhantomData<bool>,
+}
+
+impl CryptoHasher for DoubleSHA256 {
+    type Output = Digest<DoubleSHA256, 32>;
+
+    fn hash(bytes: &[u8]) -> CryptoHash {
+        SHA256::hash(&SHA256::hash(bytes).data)
+    }
+
+    fn digest_parts(parts: &[&[u8]]) -> Self::Output {
+        Digest::new(*SHA256::digest(SHA256::digest_parts(parts).as_bytes()).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::encoding::{Digestable, hex::Hex};
+
+    use super::*;
+
+    #[test]
+    fn double_sha256_test_impls(){
+        assert_eq!(DoubleSHA256::hash(b"").digest::<Hex>().to_lowercase(), "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456");
+        assert_eq!(DoubleSHA256::hash(b"hello").digest::<Hex>().to_lowercase(), "9595c9df90075148eb06860365df33584b75bff782a510c6cd4883a419833d50");
+    }
+}
@@ -1,8 +1,30 @@
-use crate::encoding::Digestable;
+use std::{cmp::Ordering, fmt, hash::{Hash, Hasher}, hint::black_box, str::FromStr};
 
-use super::{CryptoHash, Hashable};
+use crate::encoding::{Digestable, Digester, hex::Hex};
+
+use super::{CryptoHash, Hashable, ParseHashError};
 
 impl CryptoHash{
+    pub fn from_bytes(data: Vec<u8>) -> Self{
+        Self { data }
+    }
+
+    pub fn as_bytes(&self) -> &[u8]{
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8>{
+        self.data
+    }
+
+    pub fn len(&self) -> usize{
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.data.is_empty()
+    }
+
     pub(crate) fn new_32bit_word(data: &[u32], expected_bits: u16) -> Result<Self, ()>{
         let mut new = Vec::with_capacity(
             (expected_bits >> 3) as usize
@@ -19,6 +41,73 @@ impl CryptoHash{
     }
 }
 
+impl From<Vec<u8>> for CryptoHash{
+    fn from(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+}
+
+impl From<&[u8]> for CryptoHash{
+    fn from(data: &[u8]) -> Self {
+        Self { data: data.to_vec() }
+    }
+}
+
+/// Every byte is looked at even after a difference, so the time taken does
+/// not tell how much of a forged hash was right. Only the lengths are not hidden
+impl PartialEq for CryptoHash{
+    fn eq(&self, other: &Self) -> bool {
+        if self.data.len() != other.data.len() {
+            return false;
+        }
+
+        let difference = self.data.iter().zip(&other.data).fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        black_box(difference) == 0
+    }
+}
+
+impl Eq for CryptoHash{}
+
+/// By their bytes, a prefix goes first
+impl Ord for CryptoHash{
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.data.cmp(&other.data)
+    }
+}
+
+impl PartialOrd for CryptoHash{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for CryptoHash{
+    fn hash<S: Hasher>(&self, state: &mut S) {
+        Hash::hash(&self.data, state);
+    }
+}
+
+impl fmt::Display for CryptoHash{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.digest::<Hex>())
+    }
+}
+
+impl fmt::Debug for CryptoHash{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CryptoHash({})", self)
+    }
+}
+
+/// Hex in either case, like the one `Display` shows
+impl FromStr for CryptoHash{
+    type Err = ParseHashError;
+
+    fn from_str(text: &str) -> Result<Self, ParseHashError> {
+        Hex::undigest(text).map(Self::from_bytes).ok_or(ParseHashError)
+    }
+}
+
 impl Digestable for CryptoHash{
     fn bits(&self) -> &[u8] {
         &self.data
@@ -35,4 +124,57 @@ impl Hashable for String{
     fn to_bits(&self) -> &[u8] {
         self.as_bytes()
     }
-}
\ No newline at end of file
+}
+impl Hashable for [u8]{
+    fn to_bits(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Hashable for Vec<u8>{
+    fn to_bits(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Hashable for CryptoHash{
+    fn to_bits(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::{BTreeSet, HashMap};
+
+    use crate::hashers::{sha256::SHA256, CryptoHasher};
+
+    use super::*;
+
+    #[test]
+    fn compares_and_orders_by_bytes(){
+        let hash = SHA256::hash(b"hello");
+        assert_eq!(hash, SHA256::hash(b"hello"));
+        assert_ne!(hash, SHA256::hash(b"hellp"));
+        assert_ne!(CryptoHash::from_bytes(vec![1, 2]), CryptoHash::from_bytes(vec![1, 2, 0]));
+        assert!(CryptoHash::from(&[1u8, 2][..]) < CryptoHash::from(vec![1, 2, 0]));
+        assert!(CryptoHash::from(vec![2]) > CryptoHash::from(vec![1, 9]));
+
+        let roots: HashMap<CryptoHash, usize> = [(hash.clone(), 1), (SHA256::hash(b"other"), 2)].into_iter().collect();
+        assert_eq!(roots[&SHA256::hash(b"hello")], 1);
+        let sorted: BTreeSet<CryptoHash> = [vec![3], vec![1], vec![2]].into_iter().map(CryptoHash::from_bytes).collect();
+        assert_eq!(sorted.into_iter().map(CryptoHash::into_bytes).collect::<Vec<_>>(), vec![vec![1], vec![2], vec![3]]);
+    }
+
+    #[test]
+    fn shown_and_parsed_as_hex(){
+        let hash = CryptoHash::from_bytes(vec![0xAB, 0x01, 0xFF]);
+        assert_eq!(hash.to_string(), "AB01FF");
+        assert_eq!(format!("{:?}", hash), "CryptoHash(AB01FF)");
+        assert_eq!("ab01ff".parse::<CryptoHash>(), Ok(hash.clone()));
+        assert_eq!(hash.to_string().parse::<CryptoHash>().unwrap().as_bytes(), hash.as_bytes());
+        assert_eq!("AB0".parse::<CryptoHash>(), Err(ParseHashError));
+        assert_eq!("zz".parse::<CryptoHash>(), Err(ParseHashError));
+        assert_eq!((hash.len(), CryptoHash::from_bytes(vec![]).is_empty()), (3, true));
+    }
+}
@@ -0,0 +1,139 @@
+use std::marker::PhantomData;
+
+use super::{CryptoHash, CryptoHasher, digest::Digest};
+
+/// Bytes absorbed per permutation, 1600 bits of state minus twice the output size
+const RATE: usize = 136;
+
+const ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001, 0x0000000000008082, 0x800000000000808a, 0x8000000080008000,
+    0x000000000000808b, 0x0000000080000001, 0x8000000080008081, 0x8000000000008009,
+    0x000000000000008a, 0x0000000000000088, 0x0000000080008009, 0x000000008000000a,
+    0x000000008000808b, 0x800000000000008b, 0x8000000000008089, 0x8000000000008003,
+    0x8000000000008002, 0x8000000000000080, 0x000000000000800a, 0x800000008000000a,
+    0x8000000080008081, 0x8000000000008080, 0x0000000080000001, 0x8000000080008008,
+];
+
+/// Rotation of every lane, indexed as `x + 5 * y`
+const ROTATIONS: [u32; 25] = [
+    0, 1, 62, 28, 27,
+    36, 44, 6, 55, 20,
+    3, 10, 43, 25, 39,
+    41, 45, 15, 21, 8,
+    18, 2, 61, 56, 14,
+];
+
+/// Keccak-256 as Ethereum uses it, which pads with `0x01` instead of the `0x06` of the standard SHA3-256
+pub struct Keccak256 {
+    non_instance: PhantomData<bool>,
+}
+
+impl Keccak256 {
+    /// Keccak-f[1600] on the state, lanes indexed as `x + 5 * y`
+    fn permute(state: &mut [u64; 25]){
+        for round_constant in ROUND_CONSTANTS {
+            // θ
+            let mut columns = [0u64; 5];
+            for x in 0..5 {
+                columns[x] = state[x] ^ state[x + 5] ^ state[x + 10] ^ state[x + 15] ^ state[x + 20];
+            }
+            for x in 0..5 {
+                let d = columns[(x + 4) % 5] ^ columns[(x + 1) % 5].rotate_left(1);
+                for y in 0..5 {
+                    state[x + 5 * y] ^= d;
+                }
+            }
+
+            // ρ and π
+            let mut moved = [0u64; 25];
+            for x in 0..5 {
+                for y in 0..5 {
+                    moved[y + 5 * ((2 * x + 3 * y) % 5)] = state[x + 5 * y].rotate_left(ROTATIONS[x + 5 * y]);
+                }
+            }
+
+            // χ
+            for y in 0..5 {
+                for x in 0..5 {
+                    state[x + 5 * y] = moved[x + 5 * y] ^ (!moved[(x + 1) % 5 + 5 * y] & moved[(x + 2) % 5 + 5 * y]);
+                }
+            }
+
+            // ι
+            state[0] ^= round_constant;
+        }
+    }
+
+    fn absorb(state: &mut [u64; 25], block: &[u8]){
+        for (lane, word) in state.iter_mut().zip(block.chunks(8)) {
+            let mut le = [0u8; 8];
+            le.copy_from_slice(word);
+            *lane ^= u64::from_le_bytes(le);
+        }
+        Self::permute(state);
+    }
+}
+
+impl CryptoHasher for Keccak256 {
+    type Output = Digest<Keccak256, 32>;
+
+    fn hash(bytes: &[u8]) -> CryptoHash {
+        CryptoHash { data: Self::digest(bytes).as_ref().to_vec() }
+    }
+
+    /// Whole blocks are absorbed where they are, only the ones split between parts go through a buffer
+    fn digest_parts(parts: &[&[u8]]) -> Self::Output {
+        let mut state = [0u64; 25];
+        let mut block = [0u8; RATE];
+        let mut filled = 0;
+
+        for part in parts {
+            let mut part = *part;
+            while !part.is_empty() {
+                if filled == 0 && part.len() >= RATE {
+                    Self::absorb(&mut state, &part[..RATE]);
+                    part = &part[RATE..];
+                    continue;
+                }
+
+                let taken = (RATE - filled).min(part.len());
+                block[filled..filled + taken].copy_from_slice(&part[..taken]);
+                filled += taken;
+                part = &part[taken..];
+                if filled == RATE {
+                    Self::absorb(&mut state, &block);
+                    filled = 0;
+                }
+            }
+        }
+
+        block[filled..].fill(0);
+        block[filled] ^= 0x01;
+        block[RATE - 1] ^= 0x80;
+        Self::absorb(&mut state, &block);
+
+        let mut bytes = [0u8; 32];
+        for (lane, chunk) in state[..4].iter().zip(bytes.chunks_mut(8)) {
+            chunk.copy_from_slice(&lane.to_le_bytes());
+        }
+        Digest::new(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::encoding::{Digestable, hex::Hex};
+
+    use super::*;
+
+    #[test]
+    fn keccak256_test_impls(){
+        assert_eq!(Keccak256::hash(b"").digest::<Hex>().to_lowercase(), "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470");
+        assert_eq!(Keccak256::hash(b"abc").digest::<Hex>().to_lowercase(), "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45");
+        // Exactly one block, the padding goes in a block of its own
+        let block = [b'a'; RATE];
+        assert_eq!(Keccak256::hash(&block).digest::<Hex>().to_lowercase(), "a6c4d403279fe3e0af03729caada8374b5ca54d8065329a3ebcaeb4b60aa386e");
+        // Both padding bits in the same byte
+        assert_eq!(Keccak256::hash(&block[1..]).digest::<Hex>().to_lowercase(), "34367dc248bbd832f4e3e69dfaac2f92638bd0bbd18f2912ba4ef454919cf446");
+    }
+}
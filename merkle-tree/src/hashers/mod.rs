@@ -1,15 +1,63 @@
 pub mod impls;
 
 pub mod sha256;
+pub mod double_sha256;
+pub mod keccak256;
+pub mod separated;
+pub mod digest;
 pub(super) mod utils;
 
+use digest::HashOutput;
+
+/// Hash of any length, compared in constant time and shown in hex
 #[derive(Clone)]
 pub struct CryptoHash {
     pub(crate) data: Vec<u8>,
 }
 
+/// The text is not a hash in hex
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseHashError;
+
 pub trait CryptoHasher {
+    /// Fixed size type the hashes fit in
+    type Output: HashOutput;
+
     fn hash(bytes: &[u8]) -> CryptoHash;
+
+    /// Hash of a leaf of a tree, the same as `hash` unless the hasher separates leaves from nodes
+    fn hash_leaf(bytes: &[u8]) -> CryptoHash{
+        Self::hash(bytes)
+    }
+
+    /// Hash of an interior node of a tree, the same as `hash` unless the hasher separates leaves from nodes
+    fn hash_node(bytes: &[u8]) -> CryptoHash{
+        Self::hash(bytes)
+    }
+
+    /// `hash` as `Output`
+    fn digest(bytes: &[u8]) -> Self::Output{
+        Self::digest_parts(&[bytes])
+    }
+
+    /// `hash` of `parts` one after the other, as `Output`.
+    ///
+    /// This puts them together first, hashers that can take them one by one
+    /// give it without allocating
+    fn digest_parts(parts: &[&[u8]]) -> Self::Output{
+        Self::Output::from_hash(Self::hash(&parts.concat()))
+    }
+
+    /// `hash_leaf` as `Output`, hashers that change `hash_leaf` have to change this too
+    fn digest_leaf(bytes: &[u8]) -> Self::Output{
+        Self::digest(bytes)
+    }
+
+    /// `hash_node` of `first` followed by `second`, as `Output`.
+    /// Hashers that change `hash_node` have to change this too
+    fn digest_node(first: &[u8], second: &[u8]) -> Self::Output{
+        Self::digest_parts(&[first, second])
+    }
 }
 
 pub trait Hashable{
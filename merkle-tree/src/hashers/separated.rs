@@ -0,0 +1,80 @@
+use std::marker::PhantomData;
+
+use super::{CryptoHash, CryptoHasher};
+
+/// Prefixes that tell leaves and interior nodes apart
+pub trait DomainSeparation{
+    const LEAF_PREFIX: &'static [u8];
+    const NODE_PREFIX: &'static [u8];
+}
+
+/// The prefixes of RFC 6962, `0x00` for leaves and `0x01` for nodes
+pub struct ByteTags{
+    non_instance: PhantomData<bool>,
+}
+
+impl DomainSeparation for ByteTags{
+    const LEAF_PREFIX: &'static [u8] = &[0x00];
+    const NODE_PREFIX: &'static [u8] = &[0x01];
+}
+
+/// Hasher that prefixes leaves and nodes of a tree with the tags of `S` before
+/// hashing them with `H`, so an interior node can't be passed off as a leaf.
+///
+/// Anything else is hashed as `H` would.
+pub struct Separated<H: CryptoHasher, S: DomainSeparation>{
+    non_instance: PhantomData<(H, S)>,
+}
+
+impl<H: CryptoHasher, S: DomainSeparation> Separated<H, S>{
+    fn prefixed(prefix: &[u8], bytes: &[u8]) -> CryptoHash{
+        let mut prefixed = Vec::with_capacity(prefix.len() + bytes.len());
+        prefixed.extend_from_slice(prefix);
+        prefixed.extend_from_slice(bytes);
+        H::hash(&prefixed)
+    }
+}
+
+impl<H: CryptoHasher, S: DomainSeparation> CryptoHasher for Separated<H, S>{
+    type Output = H::Output;
+
+    fn hash(bytes: &[u8]) -> CryptoHash {
+        H::hash(bytes)
+    }
+
+    fn hash_leaf(bytes: &[u8]) -> CryptoHash {
+        Self::prefixed(S::LEAF_PREFIX, bytes)
+    }
+
+    fn hash_node(bytes: &[u8]) -> CryptoHash {
+        Self::prefixed(S::NODE_PREFIX, bytes)
+    }
+
+    fn digest_parts(parts: &[&[u8]]) -> Self::Output {
+        H::digest_parts(parts)
+    }
+
+    fn digest_leaf(bytes: &[u8]) -> Self::Output {
+        H::digest_parts(&[S::LEAF_PREFIX, bytes])
+    }
+
+    fn digest_node(first: &[u8], second: &[u8]) -> Self::Output {
+        H::digest_parts(&[S::NODE_PREFIX, first, second])
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::{sha256::SHA256, Hashable}, encoding::{Digestable, hex::Hex}};
+
+    use super::*;
+
+    #[test]
+    fn prefixes_leaves_and_nodes(){
+        type Tagged = Separated<SHA256, ByteTags>;
+        assert_eq!(Tagged::hash(b"abc").digest::<Hex>(), "abc".hash::<SHA256>().digest::<Hex>());
+        assert_eq!(Tagged::hash_leaf(b"abc").digest::<Hex>(), SHA256::hash(b"\x00abc").digest::<Hex>());
+        assert_eq!(Tagged::hash_node(b"abc").digest::<Hex>(), SHA256::hash(b"\x01abc").digest::<Hex>());
+        assert_ne!(Tagged::hash_leaf(b"abc").digest::<Hex>(), Tagged::hash_node(b"abc").digest::<Hex>());
+    }
+}
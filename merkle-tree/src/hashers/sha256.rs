@@ -1,6 +1,6 @@
 use std::{cmp::min, marker::PhantomData};
 
-use super::{utils::{mod_sum, rotate_right, shift_right}, CryptoHash, CryptoHasher};
+use super::{utils::{mod_sum, rotate_right, shift_right}, CryptoHash, CryptoHasher, digest::Digest};
 
 const CONSTANTS: [u32; 64] = [
     0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
@@ -31,7 +31,7 @@ impl SHA256 {
         let a  = x & y;
         let not_x = !x;
         let b = not_x & z;
-        return a ^ b;
+        a ^ b
     }
 
     #[inline(always)]
@@ -62,49 +62,51 @@ impl SHA256 {
         }
     }
 
-    /// Ensure that the message is a multiple of 512 bits.
+    /// State after hashing `parts` one after the other, padding included.
     ///
-    /// Append the bit 1 to the end of the message, followed by k zero bits,
-    /// where k is the smallest, non-negative solution to the equation
-    /// `l + 1 + k = 448 mod 512` => `l + 1000 0000 b + k = 56 mod 64`
-    /// Then append the 64-bit block that is equal to the number L expressed using a binary representation
-    fn do_chunk(hash: &mut [u32], chunk: &[u8], already_processed: u64, total_lenght: u64) {
-        if chunk.len() < 56 {
-            // This means that it needs padding but it can be padded in the same block
-            // it needs to first 56 bits in order to avoid modifying the original data
-            let mut padded = [0u8; 64];
-            //Copy the data
-            padded[..chunk.len()].copy_from_slice(chunk);
-            padded[chunk.len()] = b'\x80';
-            padded[56..64].copy_from_slice(&(total_lenght << 3).to_be_bytes());
-            Self::do_64bytes_chunk(hash, &padded);
-        } else if chunk.len() < 64 {
-            // This means that it needs padding, but it should be added as a
-            // new block because the len of the message can't be inserted in it
-            let mut padded = [0u8; 64];
-            padded[..chunk.len()].copy_from_slice(chunk);
-            padded[chunk.len()] = b'\x80';
-
-            Self::do_64bytes_chunk(hash, &padded);
-
-            let mut padding = [0u8; 64];
-            padding[56..64].copy_from_slice(&(total_lenght << 3).to_be_bytes());
-
-            Self::do_64bytes_chunk(hash, &padding);
-        } else if chunk.len() == 64 && already_processed + 64 >= total_lenght {
-            // This means is the last chunk, but the bits of the message is divisible by 512.
-            // Add a whole block of padding
-            Self::do_64bytes_chunk(hash, chunk);
-            let mut padding = [0u8; 64];
-            padding[0] = b'\x80';
-            padding[56..64].copy_from_slice(&(total_lenght << 3).to_be_bytes());
-
-            Self::do_64bytes_chunk(hash, &padding);
-        } else {
-            Self::do_64bytes_chunk(hash, chunk);
+    /// Whole blocks are hashed where they are, only the ones split between
+    /// parts and the padding go through a buffer.
+    /// The padding is the bit 1, then zeros up to 56 bytes into a block, and
+    /// then the length of the message in bits as 64 bits
+    fn compress(parts: &[&[u8]]) -> [u32; 8] {
+        let mut hash = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+        let mut block = [0u8; 64];
+        let mut filled = 0;
+        let mut total_lenght = 0u64;
+
+        for part in parts {
+            total_lenght += part.len() as u64;
+            let mut part = *part;
+            while !part.is_empty() {
+                if filled == 0 && part.len() >= 64 {
+                    Self::do_64bytes_chunk(&mut hash, &part[..64]);
+                    part = &part[64..];
+                    continue;
+                }
+
+                let taken = min(64 - filled, part.len());
+                block[filled..filled + taken].copy_from_slice(&part[..taken]);
+                filled += taken;
+                part = &part[taken..];
+                if filled == 64 {
+                    Self::do_64bytes_chunk(&mut hash, &block);
+                    filled = 0;
+                }
+            }
         }
-    }
 
+        block[filled] = b'\x80';
+        block[filled + 1..].fill(0);
+        if filled >= 56 {
+            // The length does not fit after the data, it goes in a block of its own
+            Self::do_64bytes_chunk(&mut hash, &block);
+            block = [0u8; 64];
+        }
+        block[56..64].copy_from_slice(&(total_lenght << 3).to_be_bytes());
+        Self::do_64bytes_chunk(&mut hash, &block);
+
+        hash
+    }
 
     fn do_64bytes_chunk(hash: &mut [u32], chunk: &[u8]) {
         let mut w = [0u32;64];
@@ -159,26 +161,18 @@ impl SHA256 {
 }
 
 impl CryptoHasher for SHA256 {
-    fn hash(bytes: &[u8]) -> CryptoHash {
-        let mut hash =  [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    type Output = Digest<SHA256, 32>;
 
-        if bytes.len() == 0 {
-            let mut empty_chunk = [0u8;64];
-            empty_chunk[0] = b'\x80';
-            Self::do_64bytes_chunk(&mut hash, &empty_chunk);
-        }
+    fn hash(bytes: &[u8]) -> CryptoHash {
+        CryptoHash::new_32bit_word(&Self::compress(&[bytes]), 256).expect("This should never happen")
+    }
 
-        for i in (0..bytes.len()).step_by(64) {
-            let offset = min(i + 64, bytes.len());
-            Self::do_chunk(
-                &mut hash, 
-                &bytes[i..offset], 
-                i as u64, 
-                bytes.len() as u64
-            );
+    fn digest_parts(parts: &[&[u8]]) -> Self::Output {
+        let mut bytes = [0u8; 32];
+        for (word, chunk) in Self::compress(parts).iter().zip(bytes.chunks_mut(4)) {
+            chunk.copy_from_slice(&word.to_be_bytes());
         }
-
-        return CryptoHash::new_32bit_word(&hash, 256).expect("This should never happen");
+        Digest::new(bytes)
     }
 }
 
@@ -189,6 +183,7 @@ mod test{
     use super::*;
 
     #[test]
+    #[allow(clippy::identity_op)]
     fn ch_test(){
         assert_eq!(SHA256::ch(1u32, 2u32, 3u32), (1u32 & 2u32) ^ (!1u32 & 3u32));
         assert_eq!(SHA256::ch(1234125u32, 2211234u32, 1234123u32), (1234125u32 & 2211234u32) ^ (!1234125u32 & 1234123u32));
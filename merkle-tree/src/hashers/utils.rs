@@ -12,7 +12,7 @@ pub(crate) fn shift_right<T: Number>(x:T, n: T) -> T {
 }
 
 pub(crate) fn mod_sum<T: OverflowingAdd + Zero>(numbers: &[T]) -> T {
-    if numbers.len() == 0 {
+    if numbers.is_empty() {
         return T::zero();
     }
     if numbers.len() == 1 {
@@ -0,0 +1,458 @@
+use std::marker::PhantomData;
+
+use crate::{hashers::{CryptoHash, CryptoHasher, double_sha256::DoubleSHA256, digest::Digest}, encoding::{Digestable, Digester, hex::Hex}};
+
+use super::{merkle_tree::MerkleTree, combiner::{NodeCombiner, RawBytes}, inclusion_proof::Reader, TreeShape, MerkleError, VerificationError, DecodeError};
+
+/// Hasher of the Merkle trees in Bitcoin block headers.
+///
+/// The leaves are txids, which already are hashes, so they go into the tree as
+/// they are. Nodes are the `DoubleSHA256` of both children, the lower indices first.
+/// Used with `RawBytes` and `TreeShape::PartialCopyExtend`, which duplicates the
+/// last hash of every level with an odd amount of them, it builds the same trees as Bitcoin.
+pub struct Bitcoin{
+    non_instance: PhantomData<bool>,
+}
+
+impl CryptoHasher for Bitcoin{
+    type Output = Digest<Bitcoin, 32>;
+
+    fn hash(bytes: &[u8]) -> CryptoHash {
+        DoubleSHA256::hash(bytes)
+    }
+
+    fn hash_leaf(bytes: &[u8]) -> CryptoHash {
+        CryptoHash { data: bytes.to_vec() }
+    }
+
+    fn digest_parts(parts: &[&[u8]]) -> Self::Output {
+        Digest::new(*DoubleSHA256::digest_parts(parts).as_bytes())
+    }
+
+    /// Panics if `bytes` is not a txid
+    fn digest_leaf(bytes: &[u8]) -> Self::Output {
+        Digest::from_slice(bytes).expect("A txid is 32 bytes")
+    }
+}
+
+/// Merkle root of a block with these txids, in the internal byte order. `None` if there are none.
+///
+/// Since the last hash is duplicated, a list of txids that ends repeating
+/// itself has the same root as the list without the repetition (CVE-2012-2459).
+pub fn merkle_root(txids: &[CryptoHash]) -> Option<CryptoHash>{
+    if txids.is_empty() {
+        return None;
+    }
+
+    let tree = MerkleTree::from_data::<Bitcoin, RawBytes>(txids, TreeShape::PartialCopyExtend);
+    Some(tree.root().clone())
+}
+
+/// Reads a txid or block hash the way explorers and RPCs show them, which is the internal byte order reversed
+pub fn from_display_hex(text: &str) -> Option<CryptoHash>{
+    let mut data = Hex::undigest(text)?;
+    if data.len() != 32 {
+        return None;
+    }
+
+    data.reverse();
+    Some(CryptoHash { data })
+}
+
+/// Inverse of `from_display_hex`, in lowercase like Bitcoin shows them
+pub fn to_display_hex(hash: &CryptoHash) -> String{
+    let mut data = hash.bits().to_vec();
+    data.reverse();
+    Hex::digest(&data).to_lowercase()
+}
+
+/// Partial Merkle tree of BIP 37, the proof a `merkleblock` message carries for the transactions it matched.
+///
+/// The tree is walked depth first, lower indices first. A flag bit is kept for
+/// every node visited, telling if any matched txid is below it, and a hash for
+/// every node that is not descended into and every matched txid.
+#[derive(Clone)]
+pub struct PartialMerkleTree{
+    total: u32,
+    hashes: Vec<CryptoHash>,
+    /// Padded to whole bytes when read from bytes
+    flags: Vec<bool>,
+}
+
+impl PartialMerkleTree {
+    /// Proof for the txids at the `matches` indices of a block with these txids
+    pub fn new(txids: &[CryptoHash], matches: &[usize]) -> Result<Self, MerkleError>{
+        if txids.is_empty() {
+            return Err(MerkleError::SizeOutOfBounds { size: 0, len: 0 });
+        }
+        // The amount of txids is serialized in 4 bytes
+        let total = u32::try_from(txids.len())
+            .map_err(|_| MerkleError::SizeOutOfBounds { size: txids.len(), len: u32::MAX as usize })?;
+        let mut matched = vec![false; txids.len()];
+        for &index in matches {
+            if index >= txids.len() {
+                return Err(MerkleError::IndexOutOfBounds { index, len: txids.len() });
+            }
+            matched[index] = true;
+        }
+
+        // Every level, from the txids up to the root
+        let mut levels = vec![txids.to_vec()];
+        while levels[levels.len() - 1].len() > 1 {
+            let below = &levels[levels.len() - 1];
+            let level = below.chunks(2)
+                .map(|pair| pair_hash(&pair[0], pair.last().unwrap()))
+                .collect();
+            levels.push(level);
+        }
+
+        let mut tree = Self { total, hashes: Vec::new(), flags: Vec::new() };
+        tree.build(&levels, &matched, levels.len() - 1, 0);
+        Ok(tree)
+    }
+
+    fn build(&mut self, levels: &[Vec<CryptoHash>], matched: &[bool], height: usize, position: usize){
+        let first = position << height;
+        let last = ((position + 1) << height).min(matched.len());
+        let parent_of_match = matched[first..last].contains(&true);
+        self.flags.push(parent_of_match);
+
+        if height == 0 || !parent_of_match {
+            self.hashes.push(levels[height][position].clone());
+            return;
+        }
+
+        self.build(levels, matched, height - 1, position * 2);
+        if position * 2 + 1 < levels[height - 1].len() {
+            self.build(levels, matched, height - 1, position * 2 + 1);
+        }
+    }
+
+    /// Amount of transactions in the block
+    pub fn total(&self) -> u32{
+        self.total
+    }
+
+    /// Root the proof leads to, and the matched txids with their index in the block.
+    ///
+    /// Fails if the proof does not describe a valid walk of a tree of `total` txids.
+    pub fn extract_matches(&self) -> Result<(CryptoHash, Vec<(usize, CryptoHash)>), VerificationError>{
+        let total = self.total as usize;
+        if total == 0 || self.hashes.len() > total || self.flags.len() < self.hashes.len() {
+            return Err(VerificationError::MalformedProof);
+        }
+
+        let mut height = 0;
+        while width(total, height) > 1 {
+            height += 1;
+        }
+
+        let mut walk = Walk { total, flags: 0, hashes: 0, matches: Vec::new() };
+        let root = walk.extract(self, height, 0)?;
+
+        // Every hash has to be used, and the flags only padded up to the next byte
+        if walk.hashes != self.hashes.len() || walk.flags.div_ceil(8) != self.flags.len().div_ceil(8) {
+            return Err(VerificationError::MalformedProof);
+        }
+
+        Ok((root, walk.matches))
+    }
+
+    /// Encoding of the `merkleblock` message, the total as a little endian `u32`,
+    /// then the hashes and the flag bits packed from the least significant one, each prefixed with their amount
+    pub fn to_bytes(&self) -> Vec<u8>{
+        let mut bytes = Vec::with_capacity(4 + 9 + self.hashes.len() * 32 + 9 + self.flags.len().div_ceil(8));
+        bytes.extend(self.total.to_le_bytes());
+
+        write_compact_size(&mut bytes, self.hashes.len());
+        for hash in &self.hashes {
+            bytes.extend_from_slice(hash.bits());
+        }
+
+        let mut flags = vec![0u8; self.flags.len().div_ceil(8)];
+        for (i, flag) in self.flags.iter().enumerate() {
+            flags[i / 8] |= (*flag as u8) << (i % 8);
+        }
+        write_compact_size(&mut bytes, flags.len());
+        bytes.extend(flags);
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError>{
+        let mut reader = Reader { bytes };
+        let tree = Self::read(&mut reader)?;
+        if !reader.bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+
+        Ok(tree)
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, DecodeError>{
+        let mut total = [0u8; 4];
+        total.copy_from_slice(reader.take(4)?);
+
+        let amount = read_compact_size(reader)?;
+        // Checked against what is left so a made up amount can't allocate everything
+        if amount > reader.bytes.len() / 32 {
+            return Err(DecodeError::Truncated);
+        }
+        let hashes = (0..amount)
+            .map(|_| reader.take(32).map(|hash| CryptoHash { data: hash.to_vec() }))
+            .collect::<Result<_, _>>()?;
+
+        let amount = read_compact_size(reader)?;
+        let flags = reader.take(amount)?.iter()
+            .flat_map(|byte| (0..8).map(move |bit| byte & (1 << bit) != 0))
+            .collect();
+
+        Ok(Self { total: u32::from_le_bytes(total), hashes, flags })
+    }
+}
+
+/// State of the walk of `PartialMerkleTree::extract_matches`
+struct Walk{
+    total: usize,
+    /// Amount of flags and hashes used
+    flags: usize,
+    hashes: usize,
+    matches: Vec<(usize, CryptoHash)>,
+}
+
+impl Walk {
+    fn extract(&mut self, tree: &PartialMerkleTree, height: usize, position: usize) -> Result<CryptoHash, VerificationError>{
+        let parent_of_match = *tree.flags.get(self.flags).ok_or(VerificationError::MalformedProof)?;
+        self.flags += 1;
+
+        if height == 0 || !parent_of_match {
+            let hash = tree.hashes.get(self.hashes).ok_or(VerificationError::MalformedProof)?.clone();
+            self.hashes += 1;
+            if height == 0 && parent_of_match {
+                self.matches.push((position, hash.clone()));
+            }
+            return Ok(hash);
+        }
+
+        let lower = self.extract(tree, height - 1, position * 2)?;
+        if position * 2 + 1 >= width(self.total, height - 1) {
+            return Ok(pair_hash(&lower, &lower));
+        }
+
+        let higher = self.extract(tree, height - 1, position * 2 + 1)?;
+        // Only the last node of a level can be duplicated, two equal children
+        // here would let the same root prove a different list of txids
+        if lower == higher {
+            return Err(VerificationError::MalformedProof);
+        }
+
+        Ok(pair_hash(&lower, &higher))
+    }
+}
+
+/// A block header followed by the partial Merkle tree of the transactions that matched a filter
+#[derive(Clone)]
+pub struct MerkleBlock{
+    header: [u8; 80],
+    tree: PartialMerkleTree,
+}
+
+impl MerkleBlock {
+    pub fn new(header: [u8; 80], tree: PartialMerkleTree) -> Self{
+        Self { header, tree }
+    }
+
+    pub fn header(&self) -> &[u8; 80]{
+        &self.header
+    }
+
+    pub fn tree(&self) -> &PartialMerkleTree{
+        &self.tree
+    }
+
+    /// Hash of the header, in the internal byte order
+    pub fn block_hash(&self) -> CryptoHash{
+        DoubleSHA256::hash(&self.header)
+    }
+
+    /// Merkle root the header commits to, in the internal byte order
+    pub fn merkle_root(&self) -> CryptoHash{
+        CryptoHash { data: self.header[36..68].to_vec() }
+    }
+
+    /// The matched txids with their index in the block, if the partial tree leads to the root in the header
+    pub fn matches(&self) -> Result<Vec<(usize, CryptoHash)>, VerificationError>{
+        let (root, matches) = self.tree.extract_matches()?;
+        if root != self.merkle_root() {
+            return Err(VerificationError::WrongRoot);
+        }
+
+        Ok(matches)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8>{
+        let mut bytes = self.header.to_vec();
+        bytes.extend(self.tree.to_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError>{
+        let mut reader = Reader { bytes };
+        let mut header = [0u8; 80];
+        header.copy_from_slice(reader.take(80)?);
+
+        let tree = PartialMerkleTree::read(&mut reader)?;
+        if !reader.bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+
+        Ok(Self { header, tree })
+    }
+}
+
+fn pair_hash(lower: &CryptoHash, higher: &CryptoHash) -> CryptoHash{
+    RawBytes::combine::<Bitcoin>(higher, lower)
+}
+
+/// Amount of nodes at `height` in a tree of `total` txids
+fn width(total: usize, height: usize) -> usize{
+    (total + (1 << height) - 1) >> height
+}
+
+fn write_compact_size(bytes: &mut Vec<u8>, value: usize){
+    match value {
+        0..=0xfc => bytes.push(value as u8),
+        0xfd..=0xffff => {
+            bytes.push(0xfd);
+            bytes.extend((value as u16).to_le_bytes());
+        },
+        0x10000..=0xffff_ffff => {
+            bytes.push(0xfe);
+            bytes.extend((value as u32).to_le_bytes());
+        },
+        _ => {
+            bytes.push(0xff);
+            bytes.extend((value as u64).to_le_bytes());
+        },
+    }
+}
+
+fn read_compact_size(reader: &mut Reader) -> Result<usize, DecodeError>{
+    let width = match reader.byte()? {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        small => return Ok(small as usize),
+    };
+
+    let mut le = [0u8; 8];
+    le[..width].copy_from_slice(reader.take(width)?);
+    Ok(u64::from_le_bytes(le) as usize)
+}
+
+#[cfg(test)]
+mod test{
+    use super::*;
+
+    /// Txids of block 100000
+    const BLOCK_100000: [&str; 4] = [
+        "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+        "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+        "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+        "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+    ];
+
+    /// `merkleblock` for block 100000 that matched its third transaction
+    const MERKLEBLOCK_100000: &str = "0100000050120119172a610421a6c3011dd330d9df07b63616c2cc1f1cd00200000000006657a9252aacd5c0b2940996ecff952228c3067cc38d4885efb5a4ac4247e9f337221b4d4c86041b0f2b5710040000000315b88c5107195bf09eb9da89b83d95b3d070079a3c5c5d3d17d0dcd873fbdaccc46e239ab7d28e2c019b6d66ad8fae98a56ef1f21aeecb94d1b1718186f059631d0cb83721529a062d9675b98d6e5c587e4a770fc84ed00abc5a5de04568a6e9010d";
+
+    fn txids(display: &[&str]) -> Vec<CryptoHash>{
+        display.iter().map(|txid| from_display_hex(txid).unwrap()).collect()
+    }
+
+    #[test]
+    fn block_merkle_roots(){
+        let genesis = txids(&["4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"]);
+        assert_eq!(to_display_hex(&merkle_root(&genesis).unwrap()), "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+
+        let block_170 = txids(&[
+            "b1fea52486ce0c62bb442b530a3f0132b826c74e473d1f2c220bfa78111c5082",
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+        ]);
+        assert_eq!(to_display_hex(&merkle_root(&block_170).unwrap()), "7dac2c5666815c17a3b36427de37bb9d2e2c5ccec3f8633eb91a4205cb4c10ff");
+
+        let block_100000 = txids(&BLOCK_100000);
+        assert_eq!(to_display_hex(&merkle_root(&block_100000).unwrap()), "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766");
+
+        // Odd levels duplicate their last hash
+        assert_eq!(to_display_hex(&merkle_root(&block_100000[..3]).unwrap()), "fa435470825de273081dcc706b25514c936fa6dc80ab965ce6970d68ddd0b553");
+        assert!(merkle_root(&[]).is_none());
+    }
+
+    #[test]
+    fn reads_merkleblocks(){
+        let bytes = Hex::undigest(MERKLEBLOCK_100000).unwrap();
+        let block = MerkleBlock::from_bytes(&bytes).unwrap();
+        assert_eq!(to_display_hex(&block.block_hash()), "000000000003ba27aa200b1cecaad478d2b00432346c3f1f3986da1afd33e506");
+        assert_eq!(block.tree().total(), 4);
+
+        let matches = block.matches().unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].0, 2);
+        assert_eq!(to_display_hex(&matches[0].1), BLOCK_100000[2]);
+
+        let tree = PartialMerkleTree::new(&txids(&BLOCK_100000), &[2]).unwrap();
+        assert_eq!(MerkleBlock::new(*block.header(), tree).to_bytes(), bytes);
+
+        assert_eq!(MerkleBlock::from_bytes(&bytes[..bytes.len() - 1]).err(), Some(DecodeError::Truncated));
+        assert_eq!(MerkleBlock::from_bytes(&[bytes.as_slice(), &[0]].concat()).err(), Some(DecodeError::TrailingBytes));
+    }
+
+    #[test]
+    fn partial_trees_round_trip(){
+        let mut display: Vec<String> = BLOCK_100000.iter().map(|txid| txid.to_string()).collect();
+        display.push(to_display_hex(&DoubleSHA256::hash(b"five")));
+        let txids = txids(&display.iter().map(String::as_str).collect::<Vec<_>>());
+        let root = merkle_root(&txids).unwrap();
+
+        let tree = PartialMerkleTree::new(&txids, &[0, 4]).unwrap();
+        assert_eq!(Hex::digest(&tree.to_bytes()).to_lowercase(), "0500000004876dd0a3ef4a2816ffd1c12ab649825a958b0ff3bb3d6f3e1250f13ddbf0148cc40297f730dd7b5a99567eb8d27b78758f607507c52292d02d4031895b52f2ff49aef42d78e3e9999c9e6ec9e1dddd6cb880bf3b076a03be1318ca789089308e785ec2cf1199779cb1593eac85d7465db3603834e9edd6d1420f2b369bb01dc302cf01");
+
+        for mask in 0u32..(1 << txids.len()) {
+            let matches: Vec<usize> = (0..txids.len()).filter(|i| mask & (1 << i) != 0).collect();
+            let tree = PartialMerkleTree::from_bytes(&PartialMerkleTree::new(&txids, &matches).unwrap().to_bytes()).unwrap();
+            let (extracted, found) = tree.extract_matches().unwrap();
+            assert_eq!(extracted.data, root.data);
+            let found: Vec<usize> = found.iter().map(|(index, _)| *index).collect();
+            assert_eq!(found, matches);
+        }
+
+        assert!(PartialMerkleTree::new(&txids, &[5]).is_err());
+        assert!(PartialMerkleTree::new(&[], &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_trees(){
+        let txids = txids(&BLOCK_100000);
+        let tree = PartialMerkleTree::new(&txids, &[1]).unwrap();
+
+        let mut extra = tree.clone();
+        extra.hashes.push(txids[0].clone());
+        assert_eq!(extra.extract_matches().err(), Some(VerificationError::MalformedProof));
+
+        let mut missing = tree.clone();
+        missing.hashes.pop();
+        assert_eq!(missing.extract_matches().err(), Some(VerificationError::MalformedProof));
+
+        // The last txid repeated has the same root, but not a valid proof
+        let mut repeated = txids[..3].to_vec();
+        repeated.push(txids[2].clone());
+        let tree = PartialMerkleTree::new(&repeated, &[3]).unwrap();
+        assert_eq!(tree.extract_matches().err(), Some(VerificationError::MalformedProof));
+
+        let bytes = Hex::undigest(MERKLEBLOCK_100000).unwrap();
+        let block = MerkleBlock::from_bytes(&bytes).unwrap();
+        let mut header = *block.header();
+        header[40] ^= 1;
+        assert_eq!(MerkleBlock::new(header, block.tree().clone()).matches().err(), Some(VerificationError::WrongRoot));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable};
+
+use super::{combiner::NodeCombiner, incremental::IncrementalMerkleTree, TreeShape, MerkleError};
+
+/// Computes the root of a tree one leaf at a time, without holding the leaves.
+///
+/// Leaves go into an `IncrementalMerkleTree`, which keeps only the roots of the
+/// perfect subtrees on the right edge, and the padding of the `TreeShape` is added
+/// by `finish`. `FullCopyExtend` is rejected, its padding repeats the first leaves,
+/// so it needs every leaf hash: build it with `MerkleTree::from_iter` instead.
+/// `H` and `C` must be the same ones on every call.
+pub struct MerkleBuilder<T: Hashable>{
+    tree: IncrementalMerkleTree<T>,
+}
+
+impl<T: Hashable> MerkleBuilder<T> {
+    /// Fails with `UnsupportedShape` for `FullCopyExtend`
+    pub fn new(shape: TreeShape) -> Result<Self, MerkleError>{
+        Ok(Self { tree: IncrementalMerkleTree::new(shape)? })
+    }
+
+    pub fn len(&self) -> usize{
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.tree.is_empty()
+    }
+
+    pub fn shape(&self) -> TreeShape{
+        self.tree.shape()
+    }
+
+    pub fn push<H: CryptoHasher, C: NodeCombiner>(&mut self, leaf: &T){
+        self.tree.push::<H,C>(leaf);
+    }
+
+    /// Root of all the pushed leaves, the same `MerkleTree::from_data` would have. `None` if there are none
+    pub fn finish<H: CryptoHasher, C: NodeCombiner>(self) -> Option<CryptoHash>{
+        self.tree.root::<H,C>()
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, encoding::hex::Hex, merkle::{merkle_tree::MerkleTree, combiner::RawBytes}};
+
+    use crate::merkle::testing::{data, shapes};
+    use super::*;
+
+    #[test]
+    fn same_root_as_from_data(){
+        let data = data(33);
+        for shape in shapes() {
+            if shape == TreeShape::FullCopyExtend {
+                assert!(matches!(MerkleBuilder::<String>::new(shape), Err(MerkleError::UnsupportedShape(_))));
+                continue;
+            }
+
+            for len in 1..=data.len() {
+                let mut builder = MerkleBuilder::new(shape).unwrap();
+                for leaf in &data[..len] {
+                    builder.push::<SHA256, Hex>(leaf);
+                }
+                let tree = MerkleTree::from_data::<SHA256, Hex>(&data[..len], shape);
+                assert_eq!(builder.finish::<SHA256, Hex>().unwrap().data, tree.root().data, "{:?} with {} leaves", shape, len);
+            }
+        }
+
+        assert!(MerkleBuilder::<String>::new(TreeShape::PartialNullExtend).unwrap().finish::<SHA256, Hex>().is_none());
+    }
+
+    #[test]
+    fn keeps_only_the_frontier(){
+        let mut builder = MerkleBuilder::new(TreeShape::FullNullExtend).unwrap();
+        for leaf in &data(1000) {
+            builder.push::<SHA256, RawBytes>(leaf);
+        }
+
+        // 1000 = 0b1111101000
+        assert_eq!(builder.tree.frontier.iter().flatten().count(), 6);
+    }
+
+    #[test]
+    fn from_iter_builds_the_same_tree(){
+        let data = data(13);
+        for shape in shapes() {
+            for len in 1..=data.len() {
+                let mut streamed = MerkleTree::from_iter::<SHA256, RawBytes, _>(data[..len].iter().cloned(), shape);
+                let mut tree = MerkleTree::from_data::<SHA256, RawBytes>(&data[..len], shape);
+                assert_eq!(streamed.root().data, tree.root().data, "{:?} with {} leaves", shape, len);
+                assert_eq!(streamed.len(), len);
+
+                for (i, leaf) in data[..len].iter().enumerate() {
+                    let trace = streamed.generate_trace(i).unwrap();
+                    assert_eq!(trace.verify::<SHA256, RawBytes, _>(leaf, i, tree.root()), Ok(()));
+                }
+
+                let changed = "changed".to_string();
+                streamed.update::<SHA256, RawBytes>(len - 1, &changed).unwrap();
+                tree.update::<SHA256, RawBytes>(len - 1, &changed).unwrap();
+                assert_eq!(streamed.root().data, tree.root().data, "{:?} with {} leaves", shape, len);
+            }
+        }
+    }
+}
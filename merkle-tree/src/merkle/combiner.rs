@@ -0,0 +1,108 @@
+use std::marker::PhantomData;
+
+use crate::{hashers::{CryptoHash, CryptoHasher, digest::HashOutput}, encoding::{Digestable, Digester}};
+
+/// How the hashes of two children are turned into the hash of their parent.
+///
+/// In every tree the right child holds the lower indices and the left one the higher.
+pub trait NodeCombiner{
+    fn combine<H: CryptoHasher>(left: &CryptoHash, right: &CryptoHash) -> CryptoHash;
+
+    /// `combine` of two `Output`s of `H`, as another one.
+    ///
+    /// This goes through `combine`, combiners that hash the children as they
+    /// are give it without allocating
+    fn combine_digest<H: CryptoHasher>(left: &[u8], right: &[u8]) -> H::Output{
+        H::Output::from_hash(Self::combine::<H>(&CryptoHash::from(left), &CryptoHash::from(right)))
+    }
+}
+
+/// Combiners that also hash parents with more than two children, what `KaryMerkleTree` needs.
+pub trait KaryCombiner: NodeCombiner{
+    /// Hash of a parent with any amount of children, given from the lower indices to the higher ones.
+    ///
+    /// With two children it has to be the same as `combine`.
+    fn combine_many<H: CryptoHasher>(children: &[CryptoHash]) -> CryptoHash;
+}
+
+/// Every `Digester` hashes the text it renders both children to, the left one first,
+/// or all the children from the higher indices to the lower ones.
+///
+/// This is how trees were built before there were other combiners, so the
+/// same `Digester` keeps giving the same roots.
+impl<D: Digester> NodeCombiner for D{
+    fn combine<H: CryptoHasher>(left: &CryptoHash, right: &CryptoHash) -> CryptoHash {
+        H::hash_node((left.digest::<D>() + &right.digest::<D>()).as_bytes())
+    }
+}
+
+impl<D: Digester> KaryCombiner for D{
+    fn combine_many<H: CryptoHasher>(children: &[CryptoHash]) -> CryptoHash {
+        let text: String = children.iter().rev().map(|child| child.digest::<D>()).collect();
+        H::hash_node(text.as_bytes())
+    }
+}
+
+/// Hashes the raw bytes of both children, the lower indices first.
+///
+/// The roots don't depend on any text encoding, and this is the order
+/// most other Merkle trees hash their children in.
+pub struct RawBytes{
+    non_instance: PhantomData<bool>,
+}
+
+impl NodeCombiner for RawBytes{
+    fn combine<H: CryptoHasher>(left: &CryptoHash, right: &CryptoHash) -> CryptoHash {
+        let mut children = Vec::with_capacity(left.bits().len() + right.bits().len());
+        children.extend_from_slice(right.bits());
+        children.extend_from_slice(left.bits());
+        H::hash_node(&children)
+    }
+
+    fn combine_digest<H: CryptoHasher>(left: &[u8], right: &[u8]) -> H::Output {
+        H::digest_node(right, left)
+    }
+}
+
+impl KaryCombiner for RawBytes{
+    fn combine_many<H: CryptoHasher>(children: &[CryptoHash]) -> CryptoHash {
+        let children: Vec<u8> = children.iter().flat_map(|child| child.bits()).copied().collect();
+        H::hash_node(&children)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, encoding::hex::Hex, merkle::{merkle_tree::MerkleTree, TreeShape}};
+
+    use crate::merkle::testing::data;
+    use super::*;
+
+    #[test]
+    fn raw_bytes_roots(){
+        let data = data(3);
+        let leaves: Vec<CryptoHash> = data.iter().map(|leaf| SHA256::hash(leaf.as_bytes())).collect();
+        let pair = |lower: &CryptoHash, higher: &CryptoHash| SHA256::hash(&[lower.bits(), higher.bits()].concat());
+
+        let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data[..2], TreeShape::FullNullExtend);
+        assert_eq!(tree.root().data, pair(&leaves[0], &leaves[1]).data);
+        assert_eq!(MerkleTree::from_data_raw::<SHA256>(&data[..2], TreeShape::FullNullExtend).root(), tree.root());
+
+        let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data, TreeShape::PartialCopyExtend);
+        let last = pair(&leaves[2], &leaves[2]);
+        assert_eq!(tree.root().data, pair(&pair(&leaves[0], &leaves[1]), &last).data);
+    }
+
+    #[test]
+    fn raw_bytes_trees_verify(){
+        let data = data(6);
+        let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data, TreeShape::PartialNullExtend);
+        let text = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::PartialNullExtend);
+        assert_ne!(tree.root().data, text.root().data);
+
+        for (i, leaf) in data.iter().enumerate() {
+            let trace = tree.generate_trace(i).unwrap();
+            assert_eq!(trace.verify::<SHA256, RawBytes, _>(leaf, i, tree.root()), Ok(()));
+        }
+    }
+}
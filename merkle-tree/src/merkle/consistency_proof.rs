@@ -0,0 +1,144 @@
+use crate::hashers::{CryptoHash, CryptoHasher};
+
+use super::{combiner::NodeCombiner, incremental::{root_from_peaks, partial_root_from_peaks, padding_of, nulls}, peaks::{position, rebuild}, walk::depth, TreeShape, VerificationError};
+
+/// Proof that a tree with `old_size` leaves is a prefix of one with `new_size` leaves.
+///
+/// The old tree is split in the perfect subtrees its size decomposes into, the
+/// peaks. They are enough to compute the old root, and together with the roots
+/// of the subtrees of newer leaves, to compute the new one.
+/// `FullCopyExtend` pads the old tree with copies of its first leaves, which are
+/// not nodes of the new one, so the roots of its padding are included too.
+#[derive(Clone)]
+pub struct ConsistencyProof{
+    pub(crate) old_size: usize,
+    pub(crate) new_size: usize,
+    pub(crate) shape: TreeShape,
+    /// From the biggest one, which holds the first leaves, to the smallest
+    pub(crate) peaks: Vec<CryptoHash>,
+    /// Depth first, visiting the lower indices first
+    pub(crate) hashes: Vec<CryptoHash>,
+    /// Padding of the old tree, in the order of `padding_of`. Only for `FullCopyExtend`
+    pub(crate) padding: Vec<CryptoHash>,
+}
+
+impl ConsistencyProof {
+    pub fn old_size(&self) -> usize{
+        self.old_size
+    }
+
+    pub fn new_size(&self) -> usize{
+        self.new_size
+    }
+
+    pub fn shape(&self) -> TreeShape{
+        self.shape
+    }
+
+    /// Check that `old_root` is the root of the first `old_size` leaves of the tree whose root is `new_root`.
+    ///
+    /// `H` and `C` must be the same ones the trees were built with.
+    pub fn verify<H: CryptoHasher, C: NodeCombiner>(&self, old_root: &CryptoHash, new_root: &CryptoHash) -> Result<(), VerificationError>{
+        if self.old_size == 0 || self.old_size > self.new_size {
+            return Err(VerificationError::MalformedProof);
+        }
+        if self.peaks.len() != self.old_size.count_ones() as usize {
+            return Err(VerificationError::MalformedProof);
+        }
+        let padded = match self.shape {
+            TreeShape::FullCopyExtend => padding_of(self.old_size).count(),
+            _ => 0,
+        };
+        if self.padding.len() != padded {
+            return Err(VerificationError::MalformedProof);
+        }
+
+        // Peaks are sorted by size, so the smallest level is the last one
+        let peak = |level: usize| self.peaks[position(self.old_size, level)].clone();
+
+        let old = match self.shape {
+            TreeShape::FullCopyExtend => {
+                let mut padding = vec![None; depth(self.old_size)];
+                for ((level, _), hash) in padding_of(self.old_size).zip(&self.padding) {
+                    padding[level] = Some(hash.clone());
+                }
+                root_from_peaks::<H,C>(self.old_size, peak, |level, _| padding[level].clone().unwrap())
+            },
+            TreeShape::FullNullExtend => {
+                let nulls = nulls::<H,C>(depth(self.old_size));
+                root_from_peaks::<H,C>(self.old_size, peak, |level, _| nulls[level].clone())
+            },
+            _ => partial_root_from_peaks::<H,C>(self.old_size, self.shape, peak),
+        };
+        if old != *old_root {
+            return Err(VerificationError::WrongOldRoot);
+        }
+
+        let mut hashes = self.hashes.iter();
+        let new = rebuild::<H,C>(self.old_size, &self.peaks, (0, self.new_size.next_power_of_two()), &mut hashes)?;
+        if hashes.next().is_some() {
+            return Err(VerificationError::MalformedProof);
+        }
+        if new != *new_root {
+            return Err(VerificationError::WrongRoot);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, encoding::hex::Hex, merkle::{merkle_tree::MerkleTree, MerkleError}};
+
+    use crate::merkle::testing::{data, shapes};
+    use super::*;
+
+    #[test]
+    fn proves_every_prefix(){
+        let data = data(13);
+        for shape in shapes() {
+            for new_size in 1..=data.len() {
+                let tree = MerkleTree::from_data::<SHA256, Hex>(&data[..new_size], shape);
+                for old_size in 1..=new_size {
+                    let old = MerkleTree::from_data::<SHA256, Hex>(&data[..old_size], shape);
+                    let proof = tree.consistency_proof::<SHA256, Hex>(old_size).unwrap();
+                    assert_eq!(proof.verify::<SHA256, Hex>(old.root(), tree.root()), Ok(()), "{:?} from {} to {}", shape, old_size, new_size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn detects_rewritten_history(){
+        let data = data(10);
+        let mut rewritten = data.clone();
+        rewritten[2] = "rewritten".to_string();
+
+        let old = MerkleTree::from_data::<SHA256, Hex>(&data[..6], TreeShape::PartialNullExtend);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&rewritten, TreeShape::PartialNullExtend);
+        let proof = tree.consistency_proof::<SHA256, Hex>(6).unwrap();
+        assert_eq!(proof.verify::<SHA256, Hex>(old.root(), tree.root()), Err(VerificationError::WrongOldRoot));
+
+        let other = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::PartialNullExtend);
+        let proof = other.consistency_proof::<SHA256, Hex>(6).unwrap();
+        assert_eq!(proof.verify::<SHA256, Hex>(old.root(), tree.root()), Err(VerificationError::WrongRoot));
+
+        // The copies the old tree is padded with come from the proof, they have to be the right ones
+        let old = MerkleTree::from_data::<SHA256, Hex>(&data[..5], TreeShape::FullCopyExtend);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::FullCopyExtend);
+        let mut proof = tree.consistency_proof::<SHA256, Hex>(5).unwrap();
+        assert_eq!(proof.verify::<SHA256, Hex>(old.root(), tree.root()), Ok(()));
+        proof.padding.swap(0, 1);
+        assert_eq!(proof.verify::<SHA256, Hex>(old.root(), tree.root()), Err(VerificationError::WrongOldRoot));
+        proof.padding.pop();
+        assert_eq!(proof.verify::<SHA256, Hex>(old.root(), tree.root()), Err(VerificationError::MalformedProof));
+    }
+
+    #[test]
+    fn rejects_out_of_bounds(){
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data(5), TreeShape::FullNullExtend);
+        assert_eq!(tree.consistency_proof::<SHA256, Hex>(0).err(), Some(MerkleError::SizeOutOfBounds { size: 0, len: 5 }));
+        assert!(tree.consistency_proof::<SHA256, Hex>(6).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use std::ops::Range;
+
+use crate::hashers::{CryptoHash, Hashable, digest::HashOutput};
+
+use super::{merkle_tree::MerkleTree, TreeShape, MerkleError};
+
+impl<T: Hashable, D: HashOutput> MerkleTree<T, D> {
+    /// Hashes of the nodes with `2^level` leaves under them, at `positions` among the ones of their level.
+    ///
+    /// Level 0 are the leaves and `depth()` the root. Only nodes with some of the
+    /// original leaves under them can be asked for, not the padding.
+    pub fn subtree_hashes(&self, level: usize, positions: &[usize]) -> Result<Vec<D>, MerkleError>{
+        if level > self.depth() {
+            return Err(MerkleError::LevelOutOfBounds { level, depth: self.depth() });
+        }
+
+        let width = self.len().div_ceil(1 << level);
+        positions.iter().map(|&position| {
+            if position >= width {
+                return Err(MerkleError::IndexOutOfBounds { index: position, len: width });
+            }
+            Ok(self.hash_at(level, position)?.into_owned())
+        }).collect()
+    }
+
+    /// Start reconciling with a replica whose tree has `len` leaves and `shape`,
+    /// only asking it for the hashes of the subtrees that could differ
+    pub fn sync(&self, len: usize, shape: TreeShape) -> Result<AntiEntropy<'_, T, D>, MerkleError>{
+        if len != self.len() || shape != self.shape() {
+            return Err(MerkleError::MismatchedTrees);
+        }
+
+        Ok(AntiEntropy { tree: self, level: self.depth(), pending: vec![0], different: Vec::new() })
+    }
+
+    /// Ranges of the leaves that are not the same in both trees, sorted.
+    ///
+    /// Subtrees with the same hash in both are skipped, so the cost depends on how
+    /// many leaves changed, not on the size of the trees.
+    pub fn diff(&self, other: &MerkleTree<T, D>) -> Result<Vec<Range<usize>>, MerkleError>{
+        let mut sync = self.sync(other.len(), other.shape())?;
+        while let Some((level, positions)) = sync.request() {
+            let hashes = other.subtree_hashes(level, positions)?;
+            sync.receive(&hashes)?;
+        }
+
+        Ok(sync.differences())
+    }
+}
+
+/// One side of an anti-entropy exchange between two replicas of a `MerkleTree`.
+///
+/// It walks down from the root one level at a time: every round it asks the
+/// other replica, through `MerkleTree::subtree_hashes`, for the hashes of the
+/// nodes that could differ, and only the children of the ones that do are
+/// asked for in the next round.
+pub struct AntiEntropy<'a, T: Hashable, D: HashOutput = CryptoHash>{
+    tree: &'a MerkleTree<T, D>,
+    level: usize,
+    /// Positions at `level` to ask the other replica for, sorted
+    pending: Vec<usize>,
+    /// Leaves that are known to differ
+    different: Vec<usize>,
+}
+
+impl<'a, T: Hashable, D: HashOutput> AntiEntropy<'a, T, D> {
+    /// Level and positions whose hashes the other replica has to send, `None` once done
+    pub fn request(&self) -> Option<(usize, &[usize])>{
+        if self.pending.is_empty() {
+            return None;
+        }
+
+        Some((self.level, &self.pending))
+    }
+
+    /// Compare the hashes the other replica sent for the last request
+    pub fn receive(&mut self, hashes: &[D]) -> Result<(), MerkleError>{
+        if hashes.len() != self.pending.len() {
+            return Err(MerkleError::MismatchedTrees);
+        }
+
+        let local = self.tree.subtree_hashes(self.level, &self.pending)?;
+        let differing = self.pending.iter()
+            .zip(local.iter().zip(hashes))
+            .filter(|(_, (local, remote))| local != remote)
+            .map(|(position, _)| *position);
+
+        if self.level == 0 {
+            self.different.extend(differing);
+            self.pending.clear();
+            return Ok(());
+        }
+
+        // The right child holds the lower half, both are at twice the position one level down
+        let below = self.level - 1;
+        let len = self.tree.len();
+        self.pending = differing
+            .flat_map(|position| [position * 2, position * 2 + 1])
+            .filter(|child| child << below < len)
+            .collect();
+        self.level = below;
+
+        Ok(())
+    }
+
+    pub fn is_done(&self) -> bool{
+        self.pending.is_empty()
+    }
+
+    /// Ranges of the leaves found to differ so far
+    pub fn differences(&self) -> Vec<Range<usize>>{
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for &index in &self.different {
+            match ranges.last_mut() {
+                Some(last) if last.end == index => last.end += 1,
+                _ => ranges.push(index..index + 1),
+            }
+        }
+
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::{sha256::SHA256, CryptoHasher}, merkle::combiner::RawBytes};
+
+    use crate::merkle::testing::{data, shapes};
+    use super::*;
+
+    #[test]
+    fn finds_changed_ranges(){
+        let data = data(21);
+        let mut changed = data.clone();
+        for i in [3, 4, 5, 9, 20] {
+            changed[i] = format!("changed leaf {}", i);
+        }
+
+        for shape in shapes() {
+            let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data, shape);
+            let other = MerkleTree::from_data::<SHA256, RawBytes>(&changed, shape);
+            assert_eq!(tree.diff(&other), Ok(vec![3..6, 9..10, 20..21]), "{:?}", shape);
+            assert_eq!(other.diff(&tree), Ok(vec![3..6, 9..10, 20..21]), "{:?}", shape);
+
+            let same = MerkleTree::from_data::<SHA256, RawBytes>(&data, shape);
+            assert_eq!(tree.diff(&same), Ok(vec![]));
+        }
+
+        let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data, TreeShape::PartialNullExtend);
+        let shorter = MerkleTree::from_data::<SHA256, RawBytes>(&data[..20], TreeShape::PartialNullExtend);
+        let other_shape = MerkleTree::from_data::<SHA256, RawBytes>(&data, TreeShape::PartialCopyExtend);
+        assert_eq!(tree.diff(&shorter), Err(MerkleError::MismatchedTrees));
+        assert_eq!(tree.diff(&other_shape), Err(MerkleError::MismatchedTrees));
+    }
+
+    #[test]
+    fn sync_only_exchanges_what_differs(){
+        let data = data(1000);
+        let mut changed = data.clone();
+        changed[617] = "changed".to_string();
+
+        let local = MerkleTree::from_data::<SHA256, RawBytes>(&data, TreeShape::PartialNullExtend);
+        let remote = MerkleTree::from_data::<SHA256, RawBytes>(&changed, TreeShape::PartialNullExtend);
+
+        let mut sync = local.sync(remote.len(), remote.shape()).unwrap();
+        let mut exchanged = 0;
+        let mut rounds = 0;
+        while let Some((level, positions)) = sync.request() {
+            let hashes = remote.subtree_hashes(level, positions).unwrap();
+            exchanged += hashes.len();
+            rounds += 1;
+            sync.receive(&hashes).unwrap();
+        }
+
+        assert!(sync.is_done());
+        assert_eq!(sync.differences(), vec![617..618]);
+        // The root, and then both children of every node on the path down to the leaf
+        assert_eq!(rounds, local.depth() + 1);
+        assert_eq!(exchanged, 1 + 2 * local.depth());
+    }
+
+    #[test]
+    fn subtree_hashes(){
+        let data = data(5);
+        let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data, TreeShape::PartialCopyExtend);
+        assert_eq!(tree.depth(), 3);
+        assert_eq!(tree.subtree_hashes(3, &[0]).unwrap()[0].data, tree.root().data);
+        assert_eq!(tree.subtree_hashes(0, &[4]).unwrap()[0].data, SHA256::hash(data[4].as_bytes()).data);
+
+        // Only the padding is under the fourth node of level 1
+        assert_eq!(tree.subtree_hashes(1, &[3]).err(), Some(MerkleError::IndexOutOfBounds { index: 3, len: 3 }));
+        assert_eq!(tree.subtree_hashes(4, &[0]).err(), Some(MerkleError::LevelOutOfBounds { level: 4, depth: 3 }));
+    }
+}
@@ -0,0 +1,362 @@
+use std::sync::Arc;
+
+use crate::{hashers::{CryptoHash, CryptoHasher, Hashable}, encoding::{Digestable, Digester}};
+
+use super::{node::Node, combiner::NodeCombiner, merkle_trace::MerkleTrace, TreeShape, VerificationError, DecodeError, EncodeError};
+
+/// Version written by `to_bytes`
+const VERSION: u8 = 1;
+
+/// Which side of the path a sibling hangs from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side{
+    /// The sibling holds the higher indices, the path went right
+    Left,
+    /// The sibling holds the lower indices, the path went left
+    Right,
+}
+
+#[derive(Clone)]
+pub struct Sibling{
+    pub hash: CryptoHash,
+    pub side: Side,
+}
+
+/// Flat version of a `MerkleTrace`, made to be stored or sent somewhere else.
+///
+/// # Binary encoding
+///
+/// Every integer is big endian
+///
+/// | Field     | Size       | Content                                                                      |
+/// |-----------|------------|------------------------------------------------------------------------------|
+/// | version   | 1 byte     | Always 1                                                                     |
+/// | shape     | 1 byte     | 0 `FullCopyExtend`, 1 `FullNullExtend`, 2 `PartialCopyExtend`, 3 `PartialNullExtend` |
+/// | index     | 8 bytes    | Index of the leaf                                                            |
+/// | tree size | 8 bytes    | Amount of leaves of the tree                                                 |
+/// | siblings  | 1 byte     | Amount of siblings that follow, from the leaf up to the root                 |
+///
+/// And for every sibling
+///
+/// | Field     | Size       | Content                                                                      |
+/// |-----------|------------|------------------------------------------------------------------------------|
+/// | side      | 1 byte     | 0 `Side::Left`, 1 `Side::Right`                                              |
+/// | length    | 1 byte     | Length of the hash                                                           |
+/// | hash      | length     | The hash of the sibling                                                      |
+///
+/// The text forms are the same bytes passed through a `Digester`
+#[derive(Clone)]
+pub struct InclusionProof{
+    pub(crate) index: usize,
+    pub(crate) len: usize,
+    pub(crate) shape: TreeShape,
+    pub(crate) siblings: Vec<Sibling>,
+}
+
+impl InclusionProof {
+    pub fn index(&self) -> usize{
+        self.index
+    }
+
+    /// Amount of leaves of the tree the proof was taken from
+    pub fn len(&self) -> usize{
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.len == 0
+    }
+
+    pub fn shape(&self) -> TreeShape{
+        self.shape
+    }
+
+    /// Siblings from the leaf up to the root
+    pub fn siblings(&self) -> &[Sibling]{
+        &self.siblings
+    }
+
+    /// Check that `leaf` is the value at the index of the proof, in the tree whose root is `root`.
+    ///
+    /// `H` and `C` must be the same ones the tree was built with.
+    pub fn verify<H: CryptoHasher, C: NodeCombiner, V: Hashable + ?Sized>(&self, leaf: &V, root: &CryptoHash) -> Result<(), VerificationError>{
+        self.check_path()?;
+
+        let mut current = Node::hash_leaf::<H, _>(leaf);
+        for sibling in &self.siblings {
+            current = match sibling.side {
+                Side::Left => C::combine::<H>(&sibling.hash, &current),
+                Side::Right => C::combine::<H>(&current, &sibling.hash),
+            };
+        }
+
+        if current != *root {
+            return Err(VerificationError::WrongRoot);
+        }
+
+        Ok(())
+    }
+
+    /// Rebuild the trace this proof was made from, `leaf` is the value at the index of the proof
+    pub fn to_trace<H: CryptoHasher, C: NodeCombiner, V: Hashable + ?Sized>(&self, leaf: &V) -> MerkleTrace{
+        let mut current = Arc::new(Node::leaf(Node::hash_leaf::<H, _>(leaf)));
+        for sibling in &self.siblings {
+            let other = Arc::new(Node::leaf(sibling.hash.clone()));
+            let (left, right) = match sibling.side {
+                Side::Left => (other, current),
+                Side::Right => (current, other),
+            };
+
+            current = Arc::new(Node {
+                hash: C::combine::<H>(&left.hash, &right.hash),
+                right: Some(right),
+                left: Some(left),
+            });
+        }
+
+        MerkleTrace { root: current, index: self.index, len: self.len, shape: self.shape }
+    }
+
+    /// The siblings must be one per level of the tree, on the sides the index goes through
+    fn check_path(&self) -> Result<(), VerificationError>{
+        // The size comes from the proof, it may have no power of two
+        let depth = self.len.checked_next_power_of_two().ok_or(VerificationError::MalformedProof)?.trailing_zeros() as usize;
+        if self.index >= self.len || self.siblings.len() != depth {
+            return Err(VerificationError::WrongIndex);
+        }
+
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            let went_right = (self.index >> level) & 1 == 0;
+            if went_right != (sibling.side == Side::Left) {
+                return Err(VerificationError::WrongIndex);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fails if there are more than 255 siblings, or a hash longer than 255 bytes, which their length bytes can't hold
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodeError>{
+        let amount = u8::try_from(self.siblings.len()).map_err(|_| EncodeError::TooManySiblings(self.siblings.len()))?;
+        let mut bytes = Vec::with_capacity(19 + self.siblings.len() * 34);
+        bytes.push(VERSION);
+        bytes.push(shape_tag(self.shape));
+        bytes.extend((self.index as u64).to_be_bytes());
+        bytes.extend((self.len as u64).to_be_bytes());
+        bytes.push(amount);
+        for sibling in &self.siblings {
+            bytes.push(match sibling.side {
+                Side::Left => 0,
+                Side::Right => 1,
+            });
+            let hash = sibling.hash.bits();
+            bytes.push(u8::try_from(hash.len()).map_err(|_| EncodeError::HashTooLong(hash.len()))?);
+            bytes.extend_from_slice(hash);
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError>{
+        let mut reader = Reader { bytes };
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let shape = shape_from_tag(reader.byte()?)?;
+        let index = reader.u64()? as usize;
+        let len = reader.u64()? as usize;
+        let amount = reader.byte()?;
+        let mut siblings = Vec::with_capacity(amount as usize);
+        for _ in 0..amount {
+            let side = match reader.byte()? {
+                0 => Side::Left,
+                1 => Side::Right,
+                other => return Err(DecodeError::UnknownSide(other)),
+            };
+            let length = reader.byte()? as usize;
+            let hash = CryptoHash { data: reader.take(length)?.to_vec() };
+            siblings.push(Sibling { hash, side });
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+
+        Ok(Self { index, len, shape, siblings })
+    }
+
+    /// The binary encoding written as text
+    pub fn to_text<D: Digester>(&self) -> Result<String, EncodeError>{
+        Ok(D::digest(&self.to_bytes()?))
+    }
+
+    pub fn from_text<D: Digester>(text: &str) -> Result<Self, DecodeError>{
+        let bytes = D::undigest(text).ok_or(DecodeError::InvalidText)?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl From<&MerkleTrace> for InclusionProof {
+    fn from(trace: &MerkleTrace) -> Self {
+        let path = trace.path(trace.index).expect("Traces are built with the path to their index");
+        let siblings = path.iter()
+            .rev()
+            .map(|step| Sibling {
+                hash: step.sibling.hash.clone(),
+                side: if step.went_right { Side::Left } else { Side::Right },
+            })
+            .collect();
+
+        Self { index: trace.index, len: trace.len, shape: trace.shape, siblings }
+    }
+}
+
+pub(crate) fn shape_tag(shape: TreeShape) -> u8{
+    match shape {
+        TreeShape::FullCopyExtend => 0,
+        TreeShape::FullNullExtend => 1,
+        TreeShape::PartialCopyExtend => 2,
+        TreeShape::PartialNullExtend => 3,
+    }
+}
+
+pub(crate) fn shape_from_tag(tag: u8) -> Result<TreeShape, DecodeError>{
+    match tag {
+        0 => Ok(TreeShape::FullCopyExtend),
+        1 => Ok(TreeShape::FullNullExtend),
+        2 => Ok(TreeShape::PartialCopyExtend),
+        3 => Ok(TreeShape::PartialNullExtend),
+        other => Err(DecodeError::UnknownShape(other)),
+    }
+}
+
+/// Consumes a byte slice from the front
+pub(crate) struct Reader<'a>{
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, amount: usize) -> Result<&'a [u8], DecodeError>{
+        if self.bytes.len() < amount {
+            return Err(DecodeError::Truncated);
+        }
+
+        let (taken, rest) = self.bytes.split_at(amount);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, DecodeError>{
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, DecodeError>{
+        let mut be = [0u8; 8];
+        be.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(be))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, encoding::{hex::Hex, base64::Base64}, merkle::merkle_tree::MerkleTree};
+
+    use crate::merkle::testing::{data, shapes};
+    use super::*;
+
+    #[test]
+    fn verifies_every_leaf(){
+        let data = data(7);
+        for shape in shapes() {
+            let tree = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+            for (i, leaf) in data.iter().enumerate() {
+                let proof = InclusionProof::from(&tree.generate_trace(i).unwrap());
+                assert_eq!(proof.siblings().len(), 3);
+                assert_eq!(proof.verify::<SHA256, Hex, _>(leaf, tree.root()), Ok(()));
+                assert_eq!(proof.verify::<SHA256, Hex, str>("not a leaf", tree.root()), Err(VerificationError::WrongRoot));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_bytes_and_text(){
+        let data = data(5);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::PartialNullExtend);
+        let proof = InclusionProof::from(&tree.generate_trace(3).unwrap());
+
+        let bytes = proof.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 19 + 3 * 34);
+        assert_eq!(InclusionProof::from_bytes(&bytes).unwrap().to_bytes(), Ok(bytes.clone()));
+
+        let hex = proof.to_text::<Hex>().unwrap();
+        assert_eq!(InclusionProof::from_text::<Hex>(&hex).unwrap().to_bytes(), Ok(bytes.clone()));
+        let base64 = proof.to_text::<Base64>().unwrap();
+        let decoded = InclusionProof::from_text::<Base64>(&base64).unwrap();
+        assert_eq!(decoded.to_bytes(), Ok(bytes));
+        assert_eq!(decoded.index(), 3);
+        assert_eq!(decoded.len(), 5);
+        assert_eq!(decoded.shape(), TreeShape::PartialNullExtend);
+        assert_eq!(decoded.verify::<SHA256, Hex, _>(&data[3], tree.root()), Ok(()));
+    }
+
+    #[test]
+    fn round_trips_trace(){
+        let data = data(6);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::FullCopyExtend);
+        let proof = InclusionProof::from(&tree.generate_trace(4).unwrap());
+
+        let trace = proof.to_trace::<SHA256, Hex, _>(&data[4]);
+        assert_eq!(trace.index(), 4);
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&data[4], 4, tree.root()), Ok(()));
+        assert_eq!(InclusionProof::from(&trace).to_bytes(), proof.to_bytes());
+    }
+
+    #[test]
+    fn wrong_index(){
+        let data = data(6);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::FullCopyExtend);
+        let mut proof = InclusionProof::from(&tree.generate_trace(4).unwrap());
+        proof.index = 5;
+        assert_eq!(proof.verify::<SHA256, Hex, _>(&data[4], tree.root()), Err(VerificationError::WrongIndex));
+        proof.index = 6;
+        assert_eq!(proof.verify::<SHA256, Hex, _>(&data[4], tree.root()), Err(VerificationError::WrongIndex));
+    }
+
+    #[test]
+    fn rejects_bad_encodings(){
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data(2), TreeShape::FullNullExtend);
+        let bytes = InclusionProof::from(&tree.generate_trace(1).unwrap()).to_bytes().unwrap();
+
+        let mut versioned = bytes.clone();
+        versioned[0] = 2;
+        assert_eq!(InclusionProof::from_bytes(&versioned).err(), Some(DecodeError::UnsupportedVersion(2)));
+        let mut shaped = bytes.clone();
+        shaped[1] = 9;
+        assert_eq!(InclusionProof::from_bytes(&shaped).err(), Some(DecodeError::UnknownShape(9)));
+        assert_eq!(InclusionProof::from_bytes(&bytes[..bytes.len() - 1]).err(), Some(DecodeError::Truncated));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(InclusionProof::from_bytes(&trailing).err(), Some(DecodeError::TrailingBytes));
+        assert_eq!(InclusionProof::from_text::<Hex>("not hex").err(), Some(DecodeError::InvalidText));
+    }
+
+    #[test]
+    fn rejects_what_the_encoding_cant_hold(){
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data(2), TreeShape::FullNullExtend);
+        let proof = InclusionProof::from(&tree.generate_trace(1).unwrap());
+
+        // A size with no power of two fails instead of overflowing
+        let mut huge = proof.clone();
+        huge.len = usize::MAX;
+        assert_eq!(huge.verify::<SHA256, Hex, _>(&data(2)[1], tree.root()), Err(VerificationError::MalformedProof));
+
+        let mut long = proof.clone();
+        long.siblings[0].hash = CryptoHash { data: vec![1; 256] };
+        assert_eq!(long.to_bytes(), Err(EncodeError::HashTooLong(256)));
+        let mut many = proof;
+        many.siblings = vec![many.siblings[0].clone(); 256];
+        assert_eq!(many.to_text::<Hex>(), Err(EncodeError::TooManySiblings(256)));
+    }
+}
@@ -0,0 +1,199 @@
+use std::marker::PhantomData;
+
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable};
+
+use super::{node::Node, combiner::NodeCombiner, walk::depth, TreeShape, MerkleError};
+
+/// Append only tree that only keeps the roots of the perfect subtrees on its right edge.
+///
+/// After every `push` the root is the same one `MerkleTree::from_data` would build
+/// for all the leaves pushed so far, with the same `TreeShape`.
+/// `FullCopyExtend` is not supported, its padding repeats the first leaves, so all of them would have to be kept.
+pub struct IncrementalMerkleTree<T: Hashable>{
+    /// Root of the perfect subtree of `2^level` leaves, if that bit of the length is set
+    pub(super) frontier: Vec<Option<CryptoHash>>,
+    len: usize,
+    shape: TreeShape,
+    src: PhantomData<T>
+}
+
+impl<T: Hashable> IncrementalMerkleTree<T> {
+    /// Fails with `UnsupportedShape` for `FullCopyExtend`
+    pub fn new(shape: TreeShape) -> Result<Self, MerkleError>{
+        if shape == TreeShape::FullCopyExtend {
+            return Err(MerkleError::UnsupportedShape(shape));
+        }
+
+        Ok(Self { frontier: Vec::new(), len: 0, shape, src: PhantomData })
+    }
+
+    pub fn len(&self) -> usize{
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.len == 0
+    }
+
+    pub fn shape(&self) -> TreeShape{
+        self.shape
+    }
+
+    /// Append a leaf, merging it with the subtrees of the same size before it.
+    ///
+    /// Takes O(log n) hashes. `H` and `C` must be the same ones on every push.
+    pub fn push<H: CryptoHasher, C: NodeCombiner>(&mut self, leaf: &T){
+        let hash = Node::hash_leaf::<H, _>(leaf);
+        Self::merge::<H,C>(&mut self.frontier, hash);
+        self.len += 1;
+    }
+
+    /// Root of all the leaves pushed so far, `None` if there are none.
+    ///
+    /// The padding is hashed when asked for, it takes O(log n) hashes.
+    /// `H` and `C` must be the same ones the leaves were pushed with.
+    pub fn root<H: CryptoHasher, C: NodeCombiner>(&self) -> Option<CryptoHash>{
+        if self.len == 0 {
+            return None;
+        }
+
+        let frontier = &self.frontier;
+        let root = match self.shape {
+            TreeShape::FullCopyExtend => unreachable!("Rejected by `new`"),
+            TreeShape::FullNullExtend => {
+                let nulls = nulls::<H,C>(depth(self.len));
+                root_from_peaks::<H,C>(self.len, |level| frontier[level].clone().unwrap(), |level, _| nulls[level].clone())
+            },
+            TreeShape::PartialCopyExtend | TreeShape::PartialNullExtend => {
+                partial_root_from_peaks::<H,C>(self.len, self.shape, |level| frontier[level].clone().unwrap())
+            },
+        };
+
+        Some(root)
+    }
+
+    /// Carry the new leaf up while there is a subtree of the same size to merge with
+    fn merge<H: CryptoHasher, C: NodeCombiner>(frontier: &mut Vec<Option<CryptoHash>>, hash: CryptoHash){
+        let mut carry = hash;
+        let mut level = 0;
+        while let Some(Some(lower)) = frontier.get_mut(level).map(Option::take) {
+            carry = C::combine::<H>(&carry, &lower);
+            level += 1;
+        }
+
+        if level == frontier.len() {
+            frontier.push(None);
+        }
+        frontier[level] = Some(carry);
+    }
+}
+
+/// Roots of perfect trees of null leaves, up to `depth` levels
+pub(crate) fn nulls<H: CryptoHasher, C: NodeCombiner>(depth: usize) -> Vec<CryptoHash>{
+    let mut nulls = vec![Node::null::<H>()];
+    for level in 1..=depth {
+        nulls.push(C::combine::<H>(&nulls[level - 1], &nulls[level - 1]));
+    }
+
+    nulls
+}
+
+/// Root of a tree with `len > 0` leaves, extended to the next power of two.
+///
+/// `peak(level)` is the root of the perfect subtree of `2^level` leaves the length
+/// decomposes into, and `padding(level, start)` the root of the perfect subtree of
+/// padding that starts at the leaf `start`.
+pub(crate) fn root_from_peaks<H: CryptoHasher, C: NodeCombiner>(
+    len: usize,
+    peak: impl Fn(usize) -> CryptoHash,
+    padding: impl Fn(usize, usize) -> CryptoHash,
+) -> CryptoHash{
+    let lowest = len.trailing_zeros() as usize;
+    let mut current = peak(lowest);
+    for level in lowest..depth(len) {
+        current = if level != lowest && (len >> level) & 1 == 1 {
+            C::combine::<H>(&current, &peak(level))
+        }else{
+            // The current subtree is the last one with leaves, so what follows is padding
+            let start = (((len - 1) >> level) + 1) << level;
+            C::combine::<H>(&padding(level, start), &current)
+        };
+    }
+
+    current
+}
+
+/// Level and first leaf of every subtree of padding `root_from_peaks` asks for, in the same order
+pub(crate) fn padding_of(len: usize) -> impl Iterator<Item = (usize, usize)>{
+    let lowest = len.trailing_zeros() as usize;
+    (lowest..depth(len))
+        .filter(move |level| *level == lowest || (len >> level) & 1 == 0)
+        .map(move |level| (level, (((len - 1) >> level) + 1) << level))
+}
+
+/// Same as `root_from_peaks`, but for the partial shapes, which pad with a single node at every level
+pub(crate) fn partial_root_from_peaks<H: CryptoHasher, C: NodeCombiner>(
+    len: usize,
+    shape: TreeShape,
+    peak: impl Fn(usize) -> CryptoHash,
+) -> CryptoHash{
+    let filler = match shape {
+        TreeShape::PartialNullExtend => Some(Node::null::<H>()),
+        _ => None,
+    };
+
+    let lowest = len.trailing_zeros() as usize;
+    let mut current = peak(lowest);
+    for level in lowest..depth(len) {
+        current = if level != lowest && (len >> level) & 1 == 1 {
+            C::combine::<H>(&current, &peak(level))
+        }else{
+            match &filler {
+                Some(filler) => C::combine::<H>(filler, &current),
+                None => C::combine::<H>(&current, &current),
+            }
+        };
+    }
+
+    current
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, encoding::hex::Hex, merkle::merkle_tree::MerkleTree};
+
+    use crate::merkle::testing::{data, shapes};
+    use super::*;
+
+    #[test]
+    fn same_root_as_from_data(){
+        let data = data(35);
+        for shape in shapes() {
+            if shape == TreeShape::FullCopyExtend {
+                assert!(matches!(IncrementalMerkleTree::<String>::new(shape), Err(MerkleError::UnsupportedShape(_))));
+                continue;
+            }
+
+            let mut incremental = IncrementalMerkleTree::new(shape).unwrap();
+            assert!(incremental.root::<SHA256, Hex>().is_none());
+            for (i, leaf) in data.iter().enumerate() {
+                incremental.push::<SHA256, Hex>(leaf);
+                let tree = MerkleTree::from_data::<SHA256, Hex>(&data[..=i], shape);
+                assert_eq!(incremental.len(), i + 1);
+                assert_eq!(incremental.root::<SHA256, Hex>().unwrap(), *tree.root(), "{:?} with {} leaves", shape, i + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_only_the_frontier(){
+        let mut incremental = IncrementalMerkleTree::new(TreeShape::PartialNullExtend).unwrap();
+        for leaf in &data(1000) {
+            incremental.push::<SHA256, Hex>(leaf);
+        }
+
+        // 1000 = 0b1111101000
+        let kept = incremental.frontier.iter().filter(|peak| peak.is_some()).count();
+        assert_eq!(kept, 6);
+    }
+}
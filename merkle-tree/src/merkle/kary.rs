@@ -0,0 +1,245 @@
+use std::marker::PhantomData;
+
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable};
+
+use super::{node::Node, combiner::KaryCombiner, TreeShape, MerkleError, VerificationError};
+
+/// Merkle tree where every node has `K` children, hashed together with `KaryCombiner::combine_many`.
+///
+/// The padding follows the `TreeShape` like in `MerkleTree`, with `K` children
+/// instead of two: the full shapes extend the leaves to the next power of `K`,
+/// and the partial ones fill every node that runs out of leaves with copies of
+/// its last child, or with null leaves. With `K = 2` the roots are the same as
+/// the ones of `MerkleTree`.
+pub struct KaryMerkleTree<T: Hashable, const K: usize>{
+    /// Hashes of every level, from the leaves up, without the padding of the partial shapes
+    levels: Vec<Vec<CryptoHash>>,
+    /// Leaf the partial shapes pad with
+    filler: Option<CryptoHash>,
+    original_len: usize,
+    shape: TreeShape,
+    src: PhantomData<T>
+}
+
+impl<T: Hashable, const K: usize> KaryMerkleTree<T, K> {
+    const ARITY: usize = {
+        assert!(K >= 2, "Nodes need at least two children");
+        K
+    };
+
+    /// Build the tree, `data` can't be empty
+    pub fn from_data<H: CryptoHasher, C: KaryCombiner>(data: &[T], tree_shape: TreeShape) -> Self{
+        let mut leaves: Vec<CryptoHash> = data.iter().map(Node::hash_leaf::<H, _>).collect();
+        let width = Self::ARITY.pow(depth(data.len(), Self::ARITY) as u32);
+        let null = Node::null::<H>();
+        while leaves.len() < width {
+            match tree_shape {
+                TreeShape::FullCopyExtend => leaves.push(leaves[leaves.len() - data.len()].clone()),
+                TreeShape::FullNullExtend => leaves.push(null.clone()),
+                TreeShape::PartialCopyExtend | TreeShape::PartialNullExtend => break,
+            }
+        }
+
+        let filler = match tree_shape {
+            TreeShape::PartialNullExtend => Some(null),
+            _ => None,
+        };
+
+        let mut tree = Self { levels: vec![leaves], filler, original_len: data.len(), shape: tree_shape, src: PhantomData };
+        while tree.levels[tree.levels.len() - 1].len() > 1 {
+            let below = tree.levels.len() - 1;
+            let level = (0..tree.levels[below].len().div_ceil(Self::ARITY))
+                .map(|position| C::combine_many::<H>(&tree.children(below, position)))
+                .collect();
+            tree.levels.push(level);
+        }
+
+        tree
+    }
+
+    pub fn root(&self) -> &CryptoHash{
+        &self.levels[self.levels.len() - 1][0]
+    }
+
+    pub fn len(&self) -> usize{
+        self.original_len
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.original_len == 0
+    }
+
+    pub fn shape(&self) -> TreeShape{
+        self.shape
+    }
+
+    /// Proof of the leaf at `which`, with all the other children of every node on its path
+    pub fn generate_trace(&self, which: usize) -> Result<KaryTrace<K>, MerkleError>{
+        if which >= self.original_len {
+            return Err(MerkleError::IndexOutOfBounds { index: which, len: self.original_len })
+        }
+
+        let mut siblings = Vec::with_capacity(self.levels.len() - 1);
+        let mut position = which;
+        for level in 0..self.levels.len() - 1 {
+            let mut children = self.children(level, position / K);
+            children.remove(position % K);
+            siblings.push(children);
+            position /= K;
+        }
+
+        Ok(KaryTrace { index: which, len: self.original_len, shape: self.shape, siblings })
+    }
+
+    /// The `K` children of the node at `position` of the level above `level`, padded if they run out
+    fn children(&self, level: usize, position: usize) -> Vec<CryptoHash>{
+        let nodes = &self.levels[level];
+        let start = position * K;
+        let mut children = nodes[start..(start + K).min(nodes.len())].to_vec();
+        while children.len() < K {
+            let padding = match &self.filler {
+                Some(filler) => filler.clone(),
+                None => children[children.len() - 1].clone(),
+            };
+            children.push(padding);
+        }
+
+        children
+    }
+}
+
+/// Proof of a leaf of a `KaryMerkleTree`
+#[derive(Clone)]
+pub struct KaryTrace<const K: usize>{
+    index: usize,
+    len: usize,
+    shape: TreeShape,
+    /// The `K - 1` other children of every node on the path, from the leaf up, lower indices first
+    siblings: Vec<Vec<CryptoHash>>,
+}
+
+impl<const K: usize> KaryTrace<K> {
+    /// Index of the traced leaf
+    pub fn index(&self) -> usize{
+        self.index
+    }
+
+    /// Amount of leaves of the tree the trace was taken from
+    pub fn len(&self) -> usize{
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.len == 0
+    }
+
+    pub fn shape(&self) -> TreeShape{
+        self.shape
+    }
+
+    pub fn siblings(&self) -> &[Vec<CryptoHash>]{
+        &self.siblings
+    }
+
+    /// Check that `leaf` is the value at `index` of the tree whose root is `root`.
+    ///
+    /// `H` and `C` must be the same ones the tree was built with.
+    pub fn verify<H: CryptoHasher, C: KaryCombiner, V: Hashable + ?Sized>(&self, leaf: &V, index: usize, root: &CryptoHash) -> Result<(), VerificationError>{
+        if index != self.index || index >= self.len {
+            return Err(VerificationError::WrongIndex);
+        }
+        if self.siblings.len() != depth(self.len, K) || self.siblings.iter().any(|level| level.len() != K - 1) {
+            return Err(VerificationError::MalformedProof);
+        }
+
+        let mut current = Node::hash_leaf::<H, _>(leaf);
+        let mut position = index;
+        for level in &self.siblings {
+            let mut children = level.clone();
+            children.insert(position % K, current);
+            current = C::combine_many::<H>(&children);
+            position /= K;
+        }
+
+        if current != *root {
+            return Err(VerificationError::WrongRoot);
+        }
+
+        Ok(())
+    }
+}
+
+/// Levels of a tree with `len` leaves and `arity` children per node
+fn depth(len: usize, arity: usize) -> usize{
+    let mut depth = 0;
+    let mut width = 1;
+    while width < len {
+        width *= arity;
+        depth += 1;
+    }
+
+    depth
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, encoding::hex::Hex, merkle::{merkle_tree::MerkleTree, combiner::RawBytes}};
+
+    use crate::merkle::testing::{data, shapes};
+    use super::*;
+
+    #[test]
+    fn binary_is_merkle_tree(){
+        let data = data(20);
+        for shape in shapes() {
+            for len in 1..=data.len() {
+                let kary = KaryMerkleTree::<_, 2>::from_data::<SHA256, Hex>(&data[..len], shape);
+                let tree = MerkleTree::from_data::<SHA256, Hex>(&data[..len], shape);
+                assert_eq!(kary.root().data, tree.root().data, "{:?} with {} leaves", shape, len);
+
+                let raw = KaryMerkleTree::<_, 2>::from_data::<SHA256, RawBytes>(&data[..len], shape);
+                let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data[..len], shape);
+                assert_eq!(raw.root().data, tree.root().data, "{:?} with {} leaves", shape, len);
+            }
+        }
+    }
+
+    #[test]
+    fn pads_every_node(){
+        let data = data(6);
+        let leaves: Vec<CryptoHash> = data.iter().map(|leaf| SHA256::hash(leaf.as_bytes())).collect();
+        let null = Node::null::<SHA256>();
+
+        let tree = KaryMerkleTree::<_, 4>::from_data::<SHA256, RawBytes>(&data, TreeShape::PartialCopyExtend);
+        let first = RawBytes::combine_many::<SHA256>(&leaves[..4]);
+        let second = RawBytes::combine_many::<SHA256>(&[leaves[4].clone(), leaves[5].clone(), leaves[5].clone(), leaves[5].clone()]);
+        let expected = RawBytes::combine_many::<SHA256>(&[first.clone(), second.clone(), second.clone(), second]);
+        assert_eq!(tree.root().data, expected.data);
+
+        let tree = KaryMerkleTree::<_, 4>::from_data::<SHA256, RawBytes>(&data, TreeShape::PartialNullExtend);
+        let second = RawBytes::combine_many::<SHA256>(&[leaves[4].clone(), leaves[5].clone(), null.clone(), null.clone()]);
+        let expected = RawBytes::combine_many::<SHA256>(&[first, second, null.clone(), null]);
+        assert_eq!(tree.root().data, expected.data);
+    }
+
+    #[test]
+    fn every_leaf_verifies(){
+        let data = data(30);
+        for shape in shapes() {
+            let tree = KaryMerkleTree::<_, 4>::from_data::<SHA256, RawBytes>(&data, shape);
+            for (i, leaf) in data.iter().enumerate() {
+                let trace = tree.generate_trace(i).unwrap();
+                assert_eq!(trace.siblings().len(), 3);
+                assert_eq!(trace.verify::<SHA256, RawBytes, _>(leaf, i, tree.root()), Ok(()), "{} in {:?}", i, shape);
+            }
+        }
+
+        let tree = KaryMerkleTree::<_, 16>::from_data::<SHA256, Hex>(&data, TreeShape::FullNullExtend);
+        let trace = tree.generate_trace(17).unwrap();
+        assert_eq!(trace.siblings().len(), 2);
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&data[17], 17, tree.root()), Ok(()));
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&data[16], 17, tree.root()), Err(VerificationError::WrongRoot));
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&data[17], 16, tree.root()), Err(VerificationError::WrongIndex));
+        assert!(tree.generate_trace(30).is_err());
+    }
+}
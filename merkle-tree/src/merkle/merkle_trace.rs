@@ -1,7 +1,210 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use super::node::Node;
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable};
+
+use super::{node::Node, combiner::NodeCombiner, VerificationError, TreeShape};
 
 pub struct MerkleTrace{
-    pub(crate) root: Rc<Node>,
-}
\ No newline at end of file
+    pub(crate) root: Arc<Node>,
+    pub(crate) index: usize,
+    pub(crate) len: usize,
+    pub(crate) shape: TreeShape,
+}
+
+impl MerkleTrace {
+    /// Trace from the hashes on the path of the leaf at `index`, from the leaf up:
+    /// `nodes` are the leaf and all its ancestors, and `siblings` the sibling of each but the root
+    pub(crate) fn from_hashes(index: usize, len: usize, shape: TreeShape, nodes: Vec<CryptoHash>, siblings: Vec<CryptoHash>) -> Self{
+        let mut nodes = nodes.into_iter();
+        let mut current = Arc::new(Node::leaf(nodes.next().unwrap()));
+        for (level, (hash, sibling)) in nodes.zip(siblings).enumerate() {
+            let sibling = Arc::new(Node::leaf(sibling));
+            // The right child holds the lower indices
+            let (right, left) = match (index >> level) & 1 {
+                0 => (current, sibling),
+                _ => (sibling, current),
+            };
+            current = Arc::new(Node { hash, right: Some(right), left: Some(left) });
+        }
+
+        Self { root: current, index, len, shape }
+    }
+
+    /// Index of the traced leaf
+    pub fn index(&self) -> usize{
+        self.index
+    }
+
+    /// Amount of leaves of the tree the trace was taken from
+    pub fn len(&self) -> usize{
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.len == 0
+    }
+
+    pub fn shape(&self) -> TreeShape{
+        self.shape
+    }
+
+    /// Check that `leaf` is the value at `index` of the tree whose root is `root`.
+    ///
+    /// `H` and `C` must be the same ones the tree was built with.
+    pub fn verify<H: CryptoHasher, C: NodeCombiner, V: Hashable + ?Sized>(&self, leaf: &V, index: usize, root: &CryptoHash) -> Result<(), VerificationError>{
+        let path = self.path(index)?;
+
+        let mut current = Node::hash_leaf::<H, _>(leaf);
+        match path.last() {
+            Some(step) if current != step.next.hash => {
+                // Both children of the last step are leaves, so the only hint of a wrong index is the leaf
+                // matching the other side
+                if current == step.sibling.hash {
+                    return Err(VerificationError::WrongIndex);
+                }
+                return Err(VerificationError::WrongLeaf);
+            },
+            None if current != self.root.hash => return Err(VerificationError::WrongLeaf),
+            _ => {},
+        }
+
+        for (level, step) in path.iter().rev().enumerate() {
+            current = if step.went_right {
+                C::combine::<H>(&step.sibling.hash, &current)
+            }else{
+                C::combine::<H>(&current, &step.sibling.hash)
+            };
+
+            if current != step.node.hash {
+                return Err(VerificationError::WrongSibling { level: level + 1 });
+            }
+        }
+
+        if current != *root {
+            return Err(VerificationError::WrongRoot);
+        }
+
+        Ok(())
+    }
+
+    /// Steps from the root down to the traced leaf.
+    ///
+    /// Only the nodes on the path have children, everything hanging from
+    /// them is a copy of a sibling
+    pub(crate) fn path(&self, index: usize) -> Result<Vec<Step<'_>>, VerificationError>{
+        let depth = self.depth();
+        if depth < usize::BITS as usize && index >> depth != 0 {
+            return Err(VerificationError::WrongIndex);
+        }
+
+        let mut steps = Vec::with_capacity(depth);
+        let mut node = &self.root;
+        for i in 0..depth {
+            // Walking down, the first step is the most significant bit of the index
+            let went_right = (index >> (depth - 1 - i)) & 1 == 0;
+            let (right, left) = match (&node.right, &node.left) {
+                (Some(right), Some(left)) => (right, left),
+                _ => unreachable!("The depth only counts nodes with children"),
+            };
+
+            let (next, sibling) = if went_right { (right, left) } else { (left, right) };
+            if next.is_leaf() && i + 1 < depth {
+                return Err(VerificationError::WrongIndex);
+            }
+            if !sibling.is_leaf() {
+                return Err(VerificationError::WrongSibling { level: depth - i });
+            }
+
+            steps.push(Step { node, next, sibling, went_right });
+            node = next;
+        }
+
+        Ok(steps)
+    }
+
+    fn depth(&self) -> usize{
+        let mut depth = 0;
+        let mut node = &self.root;
+        while let (Some(right), Some(left)) = (&node.right, &node.left) {
+            node = if right.is_leaf() { left } else { right };
+            depth += 1;
+        }
+
+        depth
+    }
+}
+
+pub(crate) struct Step<'a>{
+    pub(crate) node: &'a Arc<Node>,
+    pub(crate) next: &'a Arc<Node>,
+    pub(crate) sibling: &'a Arc<Node>,
+    pub(crate) went_right: bool,
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, encoding::hex::Hex, merkle::{merkle_tree::MerkleTree, TreeShape}};
+
+    use crate::merkle::testing::{data, shapes};
+    use super::*;
+
+    #[test]
+    fn every_leaf_verifies(){
+        for amount in 1..=9 {
+            let data = data(amount);
+            for shape in shapes() {
+                let tree = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+                for (i, leaf) in data.iter().enumerate() {
+                    let trace = tree.generate_trace(i).unwrap();
+                    assert_eq!(trace.verify::<SHA256, Hex, _>(leaf, i, tree.root()), Ok(()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_bounds_trace(){
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data(5), TreeShape::PartialNullExtend);
+        assert!(tree.generate_trace(5).is_err());
+    }
+
+    #[test]
+    fn wrong_leaf(){
+        let data = data(6);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::PartialCopyExtend);
+        let trace = tree.generate_trace(2).unwrap();
+        assert_eq!(trace.verify::<SHA256, Hex, str>("not a leaf", 2, tree.root()), Err(VerificationError::WrongLeaf));
+    }
+
+    #[test]
+    fn wrong_index(){
+        let data = data(6);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::FullNullExtend);
+        let trace = tree.generate_trace(2).unwrap();
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&data[2], 3, tree.root()), Err(VerificationError::WrongIndex));
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&data[2], 6, tree.root()), Err(VerificationError::WrongIndex));
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&data[2], 8, tree.root()), Err(VerificationError::WrongIndex));
+    }
+
+    #[test]
+    fn wrong_root(){
+        let data = data(6);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::FullCopyExtend);
+        let other = MerkleTree::from_data::<SHA256, Hex>(&data[1..], TreeShape::FullCopyExtend);
+        let trace = tree.generate_trace(4).unwrap();
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&data[4], 4, other.root()), Err(VerificationError::WrongRoot));
+    }
+
+    #[test]
+    fn wrong_sibling(){
+        let data = data(8);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::FullCopyExtend);
+        let mut trace = tree.generate_trace(0).unwrap();
+        // Leaf 0 goes right at every step, so the left child of the root is the top sibling
+        let root = Arc::get_mut(&mut trace.root).unwrap();
+        let sibling = Arc::get_mut(root.left.as_mut().unwrap()).unwrap();
+        sibling.hash = "forged".hash::<SHA256>();
+
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&data[0], 0, tree.root()), Err(VerificationError::WrongSibling { level: 3 }));
+    }
+}
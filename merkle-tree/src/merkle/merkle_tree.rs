@@ -1,138 +1,537 @@
-use std::{rc::Rc, marker::PhantomData};
+use std::{marker::PhantomData, borrow::{Borrow, Cow}};
 
-use crate::{hashers::{Hashable, CryptoHasher, CryptoHash}, encoding::{Digestable, Digester}};
+use crate::hashers::{Hashable, CryptoHasher, CryptoHash, digest::HashOutput};
 
-use super::{node::Node, TreeShape, merkle_trace::MerkleTrace};
+use super::{node::Node, combiner::{NodeCombiner, RawBytes}, persist::FileLevels, TreeShape, merkle_trace::MerkleTrace, multi_trace::MultiTrace, consistency_proof::ConsistencyProof, walk::{self, TreeNodes}, peaks, incremental, MerkleError};
 
-pub struct MerkleTree<T: Hashable>{
-    root: Rc<Node>,
+/// Every node is stored by level, from the leaves up, at its position among
+/// the ones of its level: the children of `(level, position)` are
+/// `(level - 1, 2 * position)`, holding the lower indices, and `(level - 1, 2 * position + 1)`.
+/// The full shapes store their padding, the partial ones work it out when asked.
+///
+/// Hashes are kept as `D`, a `Digest` keeps every level in a single allocation.
+pub struct MerkleTree<T: Hashable, D: HashOutput = CryptoHash>{
+    levels: Levels<D>,
+    /// Leaf the partial shapes pad with, copies of the last node if there is none
+    filler: Option<D>,
     original_len: usize,
-    src: PhantomData<T>
+    shape: TreeShape,
+    /// No `T` is held, so it does not decide if the tree can be shared between threads
+    src: PhantomData<fn() -> T>
+}
+
+/// Where the hashes of the levels are
+enum Levels<D: HashOutput>{
+    Memory(Vec<Vec<D>>),
+    /// Read from a file as they are asked for, see `MerkleTree::open`
+    File(FileLevels<D>),
 }
 
 impl<T: Hashable> MerkleTree<T>{
-    pub fn from_data<H: CryptoHasher, D:Digester>(data: &[T], tree_shape: TreeShape) -> Self{
-        let filler = match tree_shape{
-            TreeShape::PartialNullExtend => Some(H::hash(&[0u8;256])),
-            _ => None
+    pub fn from_data<H: CryptoHasher, C: NodeCombiner>(data: &[T], tree_shape: TreeShape) -> Self{
+        Self::from_leaves::<H, C, _>(Self::hash_leaves::<H, _>(data), tree_shape, Self::sequential)
+    }
+
+    /// Same as `from_data` with `RawBytes`, the combiner to use when there is no reason for another one
+    pub fn from_data_raw<H: CryptoHasher>(data: &[T], tree_shape: TreeShape) -> Self{
+        Self::from_data::<H, RawBytes>(data, tree_shape)
+    }
+
+    /// Same tree as `from_data`, but the leaves are hashed as they come and only their hashes are held.
+    ///
+    /// The whole tree is still kept, O(n) hashes. When the root is all that is needed,
+    /// `MerkleBuilder` keeps O(log n), for every shape but `FullCopyExtend`.
+    /// Panics if there are no leaves, like `from_data`.
+    pub fn from_iter<H: CryptoHasher, C: NodeCombiner, I>(data: I, tree_shape: TreeShape) -> Self
+    where I: IntoIterator, I::Item: Borrow<T>
+    {
+        Self::from_leaves::<H, C, _>(Self::hash_leaves::<H, _>(data), tree_shape, Self::sequential)
+    }
+
+    /// Same tree as `from_data`, with every hash kept as the `Output` of `H` instead of on the heap
+    pub fn from_data_digest<H: CryptoHasher, C: NodeCombiner>(data: &[T], tree_shape: TreeShape) -> MerkleTree<T, H::Output>{
+        let leaves = MerkleTree::<T, H::Output>::hash_leaves::<H, _>(data);
+        MerkleTree::from_leaves::<H, C, _>(leaves, tree_shape, MerkleTree::<T, H::Output>::sequential)
+    }
+
+    /// Same tree as `from_iter`, with every hash kept as the `Output` of `H` instead of on the heap
+    pub fn from_iter_digest<H: CryptoHasher, C: NodeCombiner, I>(data: I, tree_shape: TreeShape) -> MerkleTree<T, H::Output>
+    where I: IntoIterator, I::Item: Borrow<T>
+    {
+        let leaves = MerkleTree::<T, H::Output>::hash_leaves::<H, _>(data);
+        MerkleTree::from_leaves::<H, C, _>(leaves, tree_shape, MerkleTree::<T, H::Output>::sequential)
+    }
+}
+
+impl<T: Hashable, D: HashOutput> MerkleTree<T, D>{
+    /// Hash of every leaf, as `D`
+    fn hash_leaves<H: CryptoHasher, I>(data: I) -> Vec<D>
+    where I: IntoIterator, I::Item: Borrow<T>
+    {
+        data.into_iter().map(|datoid| D::leaf::<H>(datoid.borrow().to_bits())).collect()
+    }
+
+    /// Hashes every position of a level on this thread, the `map` of `from_leaves`
+    fn sequential(width: usize, hash: &(dyn Fn(usize) -> D + Sync)) -> Vec<D>{
+        (0..width).map(hash).collect()
+    }
+
+    /// Tree over the hashes of its leaves. `map` hashes every position of a level,
+    /// given their amount and what hashes one of them
+    pub(crate) fn from_leaves<H, C, M>(mut leaves: Vec<D>, tree_shape: TreeShape, map: M) -> Self
+    where H: CryptoHasher, C: NodeCombiner, M: Fn(usize, &(dyn Fn(usize) -> D + Sync)) -> Vec<D>
+    {
+        assert!(!leaves.is_empty(), "A tree needs at least one leaf");
+        let original_len = leaves.len();
+        // The same leaf as `Node::null`
+        let null = D::leaf::<H>(&[0u8; 256]);
+        match tree_shape {
+            // The extension repeats the tree from its beginning
+            TreeShape::FullCopyExtend => leaves.extend_from_within(..original_len.next_power_of_two() - original_len),
+            TreeShape::FullNullExtend => leaves.resize(original_len.next_power_of_two(), null.clone()),
+            TreeShape::PartialCopyExtend | TreeShape::PartialNullExtend => {},
+        }
+
+        let filler = match tree_shape {
+            TreeShape::PartialNullExtend => Some(null),
+            _ => None,
         };
 
-        let nodes: Vec<Rc<Node>> = match tree_shape {
-            TreeShape::FullCopyExtend | TreeShape::FullNullExtend => {
-                Self::extend::<H>(
-                    Self::nodes_from_data::<H>(&data), 
-                    tree_shape
-                )
-                .into_iter()
-                .map(|node| Rc::new(node))
-                .collect()
-            },
-            TreeShape::PartialCopyExtend | TreeShape::PartialNullExtend => {
-               Self::nodes_from_data::<H>(&data)
-                    .into_iter()
-                    .map(|node| Rc::new(node))
-                    .collect()
-            },   
+        let mut levels = Vec::with_capacity(original_len.next_power_of_two().trailing_zeros() as usize + 1);
+        levels.push(leaves);
+        let mut tree = Self { levels: Levels::Memory(levels), filler, original_len, shape: tree_shape, src: PhantomData };
+        for level in 1..=tree.depth() {
+            let hashes = map(tree.width(level - 1).div_ceil(2), &|position| tree.combine_children::<H,C>(level, position));
+            let Levels::Memory(levels) = &mut tree.levels else {
+                unreachable!("Built in memory");
+            };
+            levels.push(hashes);
+        }
+
+        tree
+    }
+
+    /// Tree whose levels are in a file
+    pub(crate) fn from_file<H: CryptoHasher>(file: FileLevels<D>, original_len: usize, shape: TreeShape) -> Self{
+        let filler = match shape {
+            TreeShape::PartialNullExtend => Some(D::from_hash(Node::null::<H>())),
+            _ => None,
+        };
+
+        Self { levels: Levels::File(file), filler, original_len, shape, src: PhantomData }
+    }
+
+    /// Read every level of a tree opened from a file into memory, so it can be updated.
+    ///
+    /// Trees already in memory are left as they are.
+    pub fn load(&mut self) -> Result<(), MerkleError>{
+        if let Levels::File(file) = &self.levels {
+            let levels = file.load().map_err(|error| MerkleError::Io(error.kind()))?;
+            self.levels = Levels::Memory(levels);
+        }
+
+        Ok(())
+    }
+
+    /// Hash of the node at `(level, position)` from the ones of its children, while the tree is built in memory
+    fn combine_children<H: CryptoHasher, C: NodeCombiner>(&self, level: usize, position: usize) -> D{
+        let left = self.hash_at(level - 1, position * 2 + 1).expect("Built in memory");
+        let right = self.hash_at(level - 1, position * 2).expect("Built in memory");
+        D::combine::<H, C>(&left, &right)
+    }
+}
+
+impl<T: Hashable, D: HashOutput> MerkleTree<T, D> {
+    pub fn root(&self) -> &D{
+        match &self.levels {
+            Levels::Memory(levels) => &levels[levels.len() - 1][0],
+            Levels::File(file) => file.root(),
+        }
+    }
+
+    pub fn len(&self) -> usize{
+        self.original_len
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.original_len == 0
+    }
+
+    pub fn shape(&self) -> TreeShape{
+        self.shape
+    }
+
+    /// Levels below the root
+    pub fn depth(&self) -> usize{
+        walk::depth(self.original_len)
+    }
+
+    /// Amount of nodes stored at `level`
+    pub(crate) fn width(&self, level: usize) -> usize{
+        match &self.levels {
+            Levels::Memory(levels) => levels[level].len(),
+            Levels::File(file) => file.width(level),
+        }
+    }
+
+    /// Node with `2^level` leaves under it, at `position` among the ones of its level.
+    ///
+    /// Past the stored ones there is at most the padding of the partial shapes,
+    /// the left sibling of the last node. Only trees opened from a file can fail, if it can't be read.
+    pub(crate) fn hash_at(&self, level: usize, position: usize) -> Result<Cow<'_, D>, MerkleError>{
+        let stored = |position: usize| match &self.levels {
+            Levels::Memory(levels) => Ok(levels[level].get(position).map(Cow::Borrowed)),
+            Levels::File(file) => file.get(level, position)
+                .map(|hash| hash.map(Cow::Owned))
+                .map_err(|error| MerkleError::Io(error.kind())),
         };
 
-        let depth: usize = f64::log2(nodes.len().next_power_of_two() as f64).floor() as usize;
+        Ok(match (stored(position)?, &self.filler) {
+            (Some(hash), _) => hash,
+            (None, Some(filler)) => Cow::Borrowed(filler),
+            (None, None) => stored(position - 1)?.unwrap(),
+        })
+    }
+
+    pub fn generate_trace(&self, which: usize) -> Result<MerkleTrace, MerkleError>{
+        walk::trace(self, which, self.original_len, self.shape)
+    }
 
-        let (_, root) = Self::make_partial_tree::<H,D>(&nodes, depth, filler);
-        Self { root, original_len: data.len(), src: PhantomData }
+    /// Replace the leaf at `index` and hash again the path up to the root
+    pub fn update<H: CryptoHasher, C: NodeCombiner>(&mut self, index: usize, value: &T) -> Result<(), MerkleError>
+    where H::Output: Into<D>
+    {
+        self.update_many::<H,C>(&[(index, value)])
     }
 
-    fn nodes_from_data<H: CryptoHasher>(data: &[T]) -> Vec<Node>{
-        let mut nodes = Vec::with_capacity(data.len().next_power_of_two());
-        for datoid in data{
-            nodes.push(Node { hash: datoid.hash::<H>(), right: None, left: None })
+    /// Replace several leaves at once, ancestors shared by them are hashed only once.
+    ///
+    /// If an index is repeated, the last value wins. Nothing changes if any index is out of bounds.
+    /// `H` and `C` must be the same ones the tree was built with.
+    /// Trees opened from a file fail with `FileBacked` until they are `load`ed.
+    pub fn update_many<H: CryptoHasher, C: NodeCombiner>(&mut self, updates: &[(usize, &T)]) -> Result<(), MerkleError>
+    where H::Output: Into<D>
+    {
+        if let Levels::File(_) = self.levels {
+            return Err(MerkleError::FileBacked);
         }
 
-        nodes
+        let hashes = walk::changed_leaves::<H, D, T>(updates, self.original_len, self.shape)?;
+        let mut changed = Vec::with_capacity(hashes.len() * self.depth());
+        walk::apply::<H, C, _>(self, &hashes, self.original_len, self.shape, &mut changed)?;
+
+        let Levels::Memory(levels) = &mut self.levels else {
+            unreachable!("Checked above");
+        };
+        for (index, hash) in hashes {
+            levels[0][index] = hash;
+        }
+        for node in changed {
+            levels[node.level][node.position] = node.hash;
+        }
+
+        Ok(())
     }
 
-    fn extend<H: CryptoHasher>(mut nodes:Vec<Node>, extend_type: TreeShape) -> Vec<Node>{
-        let original_len = nodes.len();
-        let extend_to = nodes.len().next_power_of_two();
-        let null_hash = H::hash(&[0u8;256]);
-        while nodes.len() != extend_to{
-            match extend_type{
-                TreeShape::FullCopyExtend => {
-                    let dup = nodes[nodes.len() - original_len].hash.clone();
-                    nodes.push(Node{ hash:dup, right: None, left: None});
-                },
-                TreeShape::FullNullExtend => {
-                    nodes.push(Node { hash: null_hash.clone(), right: None, left: None })
-                },
-                _ => panic!("Not an extension")
+    /// Single proof for all the leaves in `which`, every sibling they need is included once
+    pub fn generate_multi_trace(&self, which: &[usize]) -> Result<MultiTrace, MerkleError>{
+        let mut indices = which.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+        if let Some(&last) = indices.last() {
+            if last >= self.original_len {
+                return Err(MerkleError::IndexOutOfBounds { index: last, len: self.original_len })
             }
         }
 
-        nodes
+        let mut siblings = Vec::new();
+        self.collect_siblings(self.depth(), 0, &indices, &mut siblings)?;
+
+        Ok(MultiTrace { indices, len: self.original_len, shape: self.shape, siblings })
     }
 
-    fn make_partial_tree<H: CryptoHasher, D: Digester>(nodes: &[Rc<Node>], depth: usize, filler: Option<CryptoHash>) -> (usize, Rc<Node>){
-        if depth == 0{
-            return (1, nodes[0].clone());
+    /// Walks down only where there is some index to prove, the first untouched
+    /// node of every branch is a sibling. The lower half goes first
+    fn collect_siblings(&self, level: usize, position: usize, which: &[usize], siblings: &mut Vec<CryptoHash>) -> Result<(), MerkleError>{
+        if which.is_empty() {
+            siblings.push(self.hash_at(level, position)?.as_hash().into_owned());
+            return Ok(());
         }
-        let (offset, right) = Self::make_partial_tree::<H,D>(nodes, depth - 1, filler.clone());
-        // If when building the right I used all the nodes, then start duplicating
-        if offset >= nodes.len(){
-            let left = match filler{
-                Some(f) => Rc::new(Node { hash: f.clone(), right: None, left: None }),
-                None => Rc::new(Node { hash: right.hash.clone(), right: None, left: None }),
-            };
-            return (offset, Rc::new(
-                Node { 
-                    hash: (left.hash.digest::<D>() + &right.hash.digest::<D>()).hash::<H>(), 
-                    right: Some(right), 
-                    left: Some(left) 
-                }
-            ));
+        if level == 0 {
+            return Ok(());
         }
 
-        //Else build the left with what is left
-        let (more_offset, left) = Self::make_partial_tree::<H,D>(&nodes[offset..], depth - 1,filler);
-        return (offset + more_offset, Rc::new(
-            Node { 
-                hash: (left.hash.digest::<D>() + &right.hash.digest::<D>()).hash::<H>(), 
-                right: Some(right), 
-                left: Some(left) 
-            }
-        ));
+        let mid = (position * 2 + 1) << (level - 1);
+        let split = which.partition_point(|i| *i < mid);
+        self.collect_siblings(level - 1, position * 2, &which[..split], siblings)?;
+        self.collect_siblings(level - 1, position * 2 + 1, &which[split..], siblings)
+    }
+
+    /// Proof that the tree built from the first `old_size` leaves is a prefix of this one.
+    ///
+    /// `H` and `C` must be the same ones the tree was built with. Only `FullCopyExtend`
+    /// hashes with them: the old tree is padded with copies of its first leaves that
+    /// are not nodes of this one, so their roots take up to `old_size` hashes.
+    pub fn consistency_proof<H: CryptoHasher, C: NodeCombiner>(&self, old_size: usize) -> Result<ConsistencyProof, MerkleError>{
+        if old_size == 0 || old_size > self.original_len {
+            return Err(MerkleError::SizeOutOfBounds { size: old_size, len: self.original_len })
+        }
+
+        // The old tree is made of the perfect subtrees its size decomposes into
+        let peaks = peaks::decompose(old_size).into_iter()
+            .map(|(start, level)| Ok(self.hash_at(level, start >> level)?.as_hash().into_owned()))
+            .collect::<Result<_, MerkleError>>()?;
+
+        let mut hashes = Vec::new();
+        self.collect_newer(self.depth(), 0, old_size, &mut hashes)?;
+
+        let padding = match self.shape {
+            // The padding at `start` repeats the old tree from its beginning
+            TreeShape::FullCopyExtend => incremental::padding_of(old_size)
+                .map(|(level, start)| self.unaligned_root::<H,C>(level, start - old_size))
+                .collect::<Result<_, _>>()?,
+            _ => Vec::new(),
+        };
+
+        Ok(ConsistencyProof { old_size, new_size: self.original_len, shape: self.shape, peaks, hashes, padding })
+    }
+
+    /// Root of a perfect tree over the `2^level` leaves from `first`, which does not have to be a node of this one
+    fn unaligned_root<H: CryptoHasher, C: NodeCombiner>(&self, level: usize, first: usize) -> Result<CryptoHash, MerkleError>{
+        if first.is_multiple_of(1 << level) {
+            return Ok(self.hash_at(level, first >> level)?.as_hash().into_owned());
+        }
+
+        let right = self.unaligned_root::<H,C>(level - 1, first)?;
+        let left = self.unaligned_root::<H,C>(level - 1, first + (1 << (level - 1)))?;
+        Ok(C::combine::<H>(&left, &right))
+    }
+
+    /// Roots of the subtrees that only have leaves past `old_size`, in the order `ConsistencyProof` rebuilds them
+    fn collect_newer(&self, level: usize, position: usize, old_size: usize, hashes: &mut Vec<CryptoHash>) -> Result<(), MerkleError>{
+        if position << level >= old_size {
+            hashes.push(self.hash_at(level, position)?.as_hash().into_owned());
+            return Ok(());
+        }
+        if (position + 1) << level <= old_size {
+            return Ok(());
+        }
+
+        self.collect_newer(level - 1, position * 2, old_size, hashes)?;
+        self.collect_newer(level - 1, position * 2 + 1, old_size, hashes)
     }
 }
 
-impl<T: Hashable> MerkleTree<T> {
-    pub fn generate_trace(&self, which: usize) -> Result<MerkleTrace, ()>{
-        if which > self.original_len {
-            return Err(())
+impl<T: Hashable, D: HashOutput> TreeNodes for MerkleTree<T, D> {
+    type Hash = D;
+    /// Level and position
+    type Node = (usize, usize);
+    type Error = MerkleError;
+
+    fn root_node(&self) -> (usize, usize) {
+        (self.depth(), 0)
+    }
+
+    fn hash_of<'a>(&'a self, &(level, position): &'a (usize, usize)) -> Result<Cow<'a, D>, MerkleError> {
+        self.hash_at(level, position)
+    }
+
+    fn children(&self, &(level, position): &(usize, usize)) -> Result<((usize, usize), (usize, usize)), MerkleError> {
+        Ok(((level - 1, position * 2 + 1), (level - 1, position * 2)))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::{sha256::SHA256, separated::{Separated, ByteTags}, digest::Digest}, encoding::{hex::Hex, Digestable}, merkle::{inclusion_proof::InclusionProof, combiner::RawBytes, testing::allocations, VerificationError}};
+
+    use crate::merkle::testing::{data, shapes};
+    use super::*;
+
+    #[test]
+    fn stored_by_level(){
+        let data = data(5);
+        let widths = |tree: &MerkleTree<String>| (0..=tree.depth()).map(|level| tree.width(level)).collect::<Vec<_>>();
+
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::PartialCopyExtend);
+        assert_eq!(widths(&tree), vec![5, 3, 2, 1]);
+        // The left sibling of the last leaf is a copy of it, and is not stored
+        assert_eq!(tree.hash_at(0, 5).unwrap().data, tree.hash_at(0, 4).unwrap().data);
+        assert_eq!(tree.hash_at(1, 2).unwrap().data, Hex::combine::<SHA256>(&tree.hash_at(0, 5).unwrap(), &tree.hash_at(0, 4).unwrap()).data);
+
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::PartialNullExtend);
+        assert_eq!(tree.hash_at(1, 3).unwrap().data, Node::null::<SHA256>().data);
+
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::FullCopyExtend);
+        assert_eq!(widths(&tree), vec![8, 4, 2, 1]);
+        assert_eq!(tree.hash_at(0, 7).unwrap().data, tree.hash_at(0, 2).unwrap().data);
+    }
+
+    #[test]
+    fn update_matches_rebuild(){
+        for amount in 1..=9 {
+            for shape in shapes() {
+                let mut data = data(amount);
+                let mut tree = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+                for i in 0..amount {
+                    data[i] = format!("updated leaf {}", i);
+                    tree.update::<SHA256, Hex>(i, &data[i]).unwrap();
+
+                    let rebuilt = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+                    assert_eq!(tree.root().data, rebuilt.root().data, "{:?} with {} leaves", shape, amount);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn update_many_matches_rebuild(){
+        for shape in shapes() {
+            let mut data = data(11);
+            let mut tree = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+            let first = "first".to_string();
+            let second = "second".to_string();
+            let third = "third".to_string();
+            tree.update_many::<SHA256, Hex>(&[(7, &first), (2, &second), (7, &third), (10, &second)]).unwrap();
+
+            data[2] = second.clone();
+            data[7] = third.clone();
+            data[10] = second.clone();
+            let rebuilt = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+            assert_eq!(tree.root().data, rebuilt.root().data, "{:?}", shape);
+            assert_eq!(tree.generate_trace(7).unwrap().verify::<SHA256, Hex, _>(&third, 7, tree.root()), Ok(()));
         }
+    }
+
+    #[test]
+    fn update_keeps_old_traces(){
+        let data = data(6);
+        let mut tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::PartialNullExtend);
+        let old_root = tree.root().clone();
+        let old_trace = tree.generate_trace(1).unwrap();
+
+        tree.update::<SHA256, Hex>(1, &"new".to_string()).unwrap();
+        assert_eq!(old_trace.verify::<SHA256, Hex, _>(&data[1], 1, &old_root), Ok(()));
+        assert!(tree.update::<SHA256, Hex>(6, &"new".to_string()).is_err());
+    }
+
+    #[test]
+    fn interior_nodes_pass_as_leaves_without_separation(){
+        let data = data(4);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::FullNullExtend);
+
+        // The children of the root, rebuilt as leaves whose hash is the node hash
+        let digest = |i: usize| Node::hash_leaf::<SHA256, _>(&data[i]).digest::<Hex>();
+        let forged = vec![digest(1) + &digest(0), digest(3) + &digest(2)];
+        let forged_tree = MerkleTree::from_data::<SHA256, Hex>(&forged, TreeShape::FullNullExtend);
+        let trace = forged_tree.generate_trace(0).unwrap();
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&forged[0], 0, tree.root()), Ok(()));
+
+        type Tagged = Separated<SHA256, ByteTags>;
+        let tree = MerkleTree::from_data::<Tagged, Hex>(&data, TreeShape::FullNullExtend);
+        let digest = |i: usize| Node::hash_leaf::<Tagged, _>(&data[i]).digest::<Hex>();
+        let forged = vec![digest(1) + &digest(0), digest(3) + &digest(2)];
+        let forged_tree = MerkleTree::from_data::<Tagged, Hex>(&forged, TreeShape::FullNullExtend);
+        let trace = forged_tree.generate_trace(0).unwrap();
+        assert_eq!(trace.verify::<Tagged, Hex, _>(&forged[0], 0, tree.root()), Err(VerificationError::WrongRoot));
+    }
 
-        return Ok(self.trace(which));
+    #[test]
+    fn separated_trees_verify(){
+        type Tagged = Separated<SHA256, ByteTags>;
+        let data = data(7);
+        for shape in shapes() {
+            let tree = MerkleTree::from_data::<Tagged, Hex>(&data, shape);
+            let plain = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+            assert_ne!(tree.root().data, plain.root().data);
+
+            for (i, leaf) in data.iter().enumerate() {
+                let trace = tree.generate_trace(i).unwrap();
+                assert_eq!(trace.verify::<Tagged, Hex, _>(leaf, i, tree.root()), Ok(()));
+                assert_eq!(InclusionProof::from(&trace).verify::<Tagged, Hex, _>(leaf, tree.root()), Ok(()));
+            }
+            let multi = tree.generate_multi_trace(&[1, 5]).unwrap();
+            assert_eq!(multi.verify::<Tagged, Hex, _>(&[(1, &data[1]), (5, &data[5])], tree.root()), Ok(()));
+            let old = MerkleTree::from_data::<Tagged, Hex>(&data[..3], shape);
+            assert_eq!(tree.consistency_proof::<Tagged, Hex>(3).unwrap().verify::<Tagged, Hex>(old.root(), tree.root()), Ok(()));
+        }
     }
 
-    fn trace(&self, which: usize) -> MerkleTrace{
-        let root = Self::search(self.root.clone(), which, 0, self.original_len.next_power_of_two());
+    #[test]
+    fn digest_trees_are_the_same_trees(){
+        let mut data = data(13);
+        for shape in shapes() {
+            let plain = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+            let mut tree = MerkleTree::from_data_digest::<SHA256, Hex>(&data, shape);
+            let root: Digest<SHA256, 32> = *tree.root();
+            assert_eq!(root.as_ref(), plain.root().data.as_slice(), "{:?}", shape);
+            assert_eq!(*MerkleTree::<String>::from_iter_digest::<SHA256, Hex, _>(&data, shape).root(), root);
+
+            let root = CryptoHash::from(root);
+            for (i, leaf) in data.iter().enumerate() {
+                assert_eq!(tree.generate_trace(i).unwrap().verify::<SHA256, Hex, _>(leaf, i, &root), Ok(()), "{} in {:?}", i, shape);
+            }
 
-        MerkleTrace { root }
+            data[4] = "changed".to_string();
+            tree.update::<SHA256, Hex>(4, &data[4]).unwrap();
+            assert_eq!(*tree.root(), *MerkleTree::from_data_digest::<SHA256, Hex>(&data, shape).root());
+            assert_eq!(tree.diff(&MerkleTree::from_data_digest::<SHA256, Hex>(&data, shape)), Ok(vec![]));
+        }
     }
 
-    fn search(root: Rc<Node>, which: usize, left: usize, rigth: usize) -> Rc<Node>{
-        if root.right.is_none() && root.left.is_none() {
-            //By construction, is either of those is none, the other is also
-            return Rc::new(Node{hash: root.hash.clone(), right: None, left: None});
+    #[test]
+    fn digest_trees_hash_without_allocating(){
+        let data = data(1000);
+        let changed = "changed".to_string();
+        for shape in shapes() {
+            let (plain, plain_allocations) = allocations(|| MerkleTree::from_data::<SHA256, RawBytes>(&data, shape));
+            let (mut tree, tree_allocations) = allocations(|| MerkleTree::from_data_digest::<SHA256, RawBytes>(&data, shape));
+            assert_eq!(tree.root().as_ref(), plain.root().data.as_slice());
+            // One per level and the list of them, the leaves are copied once more for the full shapes
+            assert!(tree_allocations <= tree.depth() + 3, "{} for {:?}", tree_allocations, shape);
+            assert!(plain_allocations > data.len(), "{:?}", shape);
+
+            let ((), update_allocations) = allocations(|| tree.update::<SHA256, RawBytes>(7, &changed).unwrap());
+            assert!(update_allocations <= 2, "{} for {:?}", update_allocations, shape);
         }
-        
-        // Always a power of two
-        let mid = (left + rigth ) / 2;
-        if which < mid  {
-            let rigth = Self::search(root.right.clone().unwrap(), which, mid, rigth);
-            let left = Rc::new(Node {hash:root.left.clone().unwrap().hash.clone(), right: None, left: None});
-            return Rc::new(Node { hash: root.hash.clone(), right: Some(rigth), left: Some(left) })
+    }
+
+    #[test]
+    fn traces_from_many_threads(){
+        fn shareable<S: Send + Sync>(){}
+        shareable::<MerkleTree<String>>();
+        shareable::<MerkleTrace>();
+        // Even for leaves that can't cross threads, since the tree does not hold them
+        struct Local(std::rc::Rc<Vec<u8>>);
+        impl Hashable for Local{
+            fn to_bits(&self) -> &[u8] {
+                &self.0
+            }
         }
+        shareable::<MerkleTree<Local>>();
 
-        let rigth = Rc::new(Node {hash:root.right.clone().unwrap().hash.clone(), right: None, left: None});
-        let left = Self::search(root.left.clone().unwrap(), which, left, mid);
-        return Rc::new(Node { hash: root.hash.clone(), right: Some(rigth), left: Some(left) });
+        let data = data(50);
+        for shape in shapes() {
+            let tree = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+            let traces: Vec<MerkleTrace> = std::thread::scope(|scope| {
+                let handles: Vec<_> = (0..4)
+                    .map(|worker| {
+                        let tree = &tree;
+                        scope.spawn(move || (worker..tree.len()).step_by(4).map(|i| tree.generate_trace(i).unwrap()).collect::<Vec<_>>())
+                    })
+                    .collect();
+                handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+            });
+
+            assert_eq!(traces.len(), data.len());
+            for trace in traces {
+                let i = trace.index();
+                assert_eq!(trace.verify::<SHA256, Hex, _>(&data[i], i, tree.root()), Ok(()));
+            }
+
+            // Moved into a worker and back
+            let moved = std::thread::spawn(move || tree).join().unwrap();
+            assert!(moved.generate_trace(49).is_ok());
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,310 @@
+use std::marker::PhantomData;
+
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable};
+
+use super::{node::Node, combiner::NodeCombiner, peaks::{decompose, bag, rebuild, push_aligned}, MerkleError, VerificationError};
+
+/// Merkle Mountain Range, an append only list of perfect trees that is never padded nor rebalanced.
+///
+/// The length decomposes in powers of two, and the leaves in perfect trees of
+/// those sizes, the peaks, from the biggest one to the smallest. Appending only
+/// merges the peaks at the end, so every node, once hashed, stays the same.
+/// The root bags the peaks from the smallest one, each bigger one holding the
+/// lower indices, so it is the right child like in every other tree.
+pub struct MerkleMountainRange<T: Hashable>{
+    /// Roots of the aligned perfect subtrees of `2^level` leaves, by level
+    levels: Vec<Vec<CryptoHash>>,
+    src: PhantomData<T>
+}
+
+impl<T: Hashable> MerkleMountainRange<T> {
+    pub fn new() -> Self{
+        Self { levels: vec![Vec::new()], src: PhantomData }
+    }
+
+    pub fn from_data<H: CryptoHasher, C: NodeCombiner>(data: &[T]) -> Self{
+        let mut mmr = Self::new();
+        for datoid in data {
+            mmr.push::<H,C>(datoid);
+        }
+
+        mmr
+    }
+
+    pub fn len(&self) -> usize{
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.levels[0].is_empty()
+    }
+
+    /// Append a leaf, returning its index
+    pub fn push<H: CryptoHasher, C: NodeCombiner>(&mut self, leaf: &T) -> usize{
+        push_aligned(&mut self.levels, Node::hash_leaf::<H, _>(leaf), |lower, higher| C::combine::<H>(higher, lower));
+        self.len() - 1
+    }
+
+    /// Peaks of the whole range, from the biggest one to the smallest
+    pub fn peaks(&self) -> Vec<CryptoHash>{
+        self.peaks_at(self.len())
+    }
+
+    /// Root of all the leaves, `None` if there are none
+    pub fn root<H: CryptoHasher, C: NodeCombiner>(&self) -> Option<CryptoHash>{
+        self.root_at::<H,C>(self.len()).ok()
+    }
+
+    /// Root the range had when it had only its first `size` leaves
+    pub fn root_at<H: CryptoHasher, C: NodeCombiner>(&self, size: usize) -> Result<CryptoHash, MerkleError>{
+        self.check_size(size)?;
+        Ok(bag::<H,C>(&self.peaks_at(size)))
+    }
+
+    /// Proof of the leaf at `index` against the current root
+    pub fn prove(&self, index: usize) -> Result<MmrProof, MerkleError>{
+        self.prove_at(index, self.len())
+    }
+
+    /// Proof of the leaf at `index` against the root the range had with `size` leaves
+    pub fn prove_at(&self, index: usize, size: usize) -> Result<MmrProof, MerkleError>{
+        self.check_size(size)?;
+        if index >= size {
+            return Err(MerkleError::IndexOutOfBounds { index, len: size });
+        }
+
+        let (position, level) = peak_of(index, size);
+        let siblings = (0..level).map(|below| self.levels[below][(index >> below) ^ 1].clone()).collect();
+        let mut peaks = self.peaks_at(size);
+        peaks.remove(position);
+
+        Ok(MmrProof { index, size, siblings, peaks })
+    }
+
+    /// Proof that the range of the first `old_size` leaves is a prefix of the current one
+    pub fn consistency_proof(&self, old_size: usize) -> Result<MmrConsistencyProof, MerkleError>{
+        self.check_size(old_size)?;
+
+        let mut hashes = Vec::new();
+        for (start, level) in decompose(self.len()) {
+            self.collect_newer(old_size, start, start + (1 << level), level, &mut hashes);
+        }
+
+        Ok(MmrConsistencyProof { old_size, new_size: self.len(), peaks: self.peaks_at(old_size), hashes })
+    }
+
+    /// Roots of the biggest subtrees in [left, rigth) with only leaves from `old_size` on, lower indices first
+    fn collect_newer(&self, old_size: usize, left: usize, rigth: usize, level: usize, hashes: &mut Vec<CryptoHash>){
+        if rigth <= old_size {
+            return;
+        }
+        if left >= old_size {
+            hashes.push(self.levels[level][left >> level].clone());
+            return;
+        }
+
+        let mid = (left + rigth) / 2;
+        self.collect_newer(old_size, left, mid, level - 1, hashes);
+        self.collect_newer(old_size, mid, rigth, level - 1, hashes);
+    }
+
+    fn peaks_at(&self, size: usize) -> Vec<CryptoHash>{
+        decompose(size).into_iter()
+            .map(|(start, level)| self.levels[level][start >> level].clone())
+            .collect()
+    }
+
+    fn check_size(&self, size: usize) -> Result<(), MerkleError>{
+        if size == 0 || size > self.len() {
+            return Err(MerkleError::SizeOutOfBounds { size, len: self.len() });
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Hashable> Default for MerkleMountainRange<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Proof of a leaf of a `MerkleMountainRange`
+#[derive(Clone)]
+pub struct MmrProof{
+    index: usize,
+    size: usize,
+    /// Inside the peak of the leaf, from the leaf up
+    siblings: Vec<CryptoHash>,
+    /// Every other peak, from the biggest one to the smallest
+    peaks: Vec<CryptoHash>,
+}
+
+impl MmrProof {
+    pub fn index(&self) -> usize{
+        self.index
+    }
+
+    /// Amount of leaves of the range the proof was made for
+    pub fn size(&self) -> usize{
+        self.size
+    }
+
+    /// Check that `leaf` is the value at `index` of the range whose root is `root`.
+    ///
+    /// `H` and `C` must be the same ones the range was built with.
+    pub fn verify<H: CryptoHasher, C: NodeCombiner, V: Hashable + ?Sized>(&self, leaf: &V, index: usize, root: &CryptoHash) -> Result<(), VerificationError>{
+        if index != self.index || index >= self.size {
+            return Err(VerificationError::WrongIndex);
+        }
+
+        let (position, level) = peak_of(index, self.size);
+        if self.siblings.len() != level || self.peaks.len() + 1 != self.size.count_ones() as usize {
+            return Err(VerificationError::MalformedProof);
+        }
+
+        let mut current = Node::hash_leaf::<H, _>(leaf);
+        for (below, sibling) in self.siblings.iter().enumerate() {
+            current = if (index >> below) & 1 == 1 {
+                C::combine::<H>(&current, sibling)
+            }else{
+                C::combine::<H>(sibling, &current)
+            };
+        }
+
+        let mut peaks = self.peaks.clone();
+        peaks.insert(position, current);
+        if bag::<H,C>(&peaks) != *root {
+            return Err(VerificationError::WrongRoot);
+        }
+
+        Ok(())
+    }
+}
+
+/// Proof that a `MerkleMountainRange` of `old_size` leaves is a prefix of one of `new_size`.
+///
+/// Every old peak is a node of the new range, so the old peaks and the roots of
+/// the subtrees of newer leaves are enough to compute both roots.
+#[derive(Clone)]
+pub struct MmrConsistencyProof{
+    old_size: usize,
+    new_size: usize,
+    /// From the biggest one to the smallest
+    peaks: Vec<CryptoHash>,
+    /// Depth first, visiting the lower indices first
+    hashes: Vec<CryptoHash>,
+}
+
+impl MmrConsistencyProof {
+    pub fn old_size(&self) -> usize{
+        self.old_size
+    }
+
+    pub fn new_size(&self) -> usize{
+        self.new_size
+    }
+
+    /// Check that `old_root` is the root of the first `old_size` leaves of the range whose root is `new_root`.
+    ///
+    /// `H` and `C` must be the same ones the ranges were built with.
+    pub fn verify<H: CryptoHasher, C: NodeCombiner>(&self, old_root: &CryptoHash, new_root: &CryptoHash) -> Result<(), VerificationError>{
+        if self.old_size == 0 || self.old_size > self.new_size || self.peaks.len() != self.old_size.count_ones() as usize {
+            return Err(VerificationError::MalformedProof);
+        }
+        if bag::<H,C>(&self.peaks) != *old_root {
+            return Err(VerificationError::WrongOldRoot);
+        }
+
+        let mut hashes = self.hashes.iter();
+        let mut peaks = Vec::new();
+        for (start, level) in decompose(self.new_size) {
+            peaks.push(rebuild::<H,C>(self.old_size, &self.peaks, (start, start + (1 << level)), &mut hashes)?);
+        }
+        if hashes.next().is_some() {
+            return Err(VerificationError::MalformedProof);
+        }
+        if bag::<H,C>(&peaks) != *new_root {
+            return Err(VerificationError::WrongRoot);
+        }
+
+        Ok(())
+    }
+}
+
+/// Position among the peaks, and level, of the peak that holds the leaf at `index`
+fn peak_of(index: usize, size: usize) -> (usize, usize){
+    decompose(size).into_iter()
+        .enumerate()
+        .find(|(_, (start, level))| index < start + (1 << level))
+        .map(|(position, (_, level))| (position, level))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::{sha256::SHA256, separated::{Separated, ByteTags}}, merkle::combiner::RawBytes};
+
+    use crate::merkle::testing::data;
+    use super::*;
+
+    type Tagged = Separated<SHA256, ByteTags>;
+
+    #[test]
+    fn never_pads(){
+        let data = data(7);
+        let mmr = MerkleMountainRange::from_data::<Tagged, RawBytes>(&data);
+        assert_eq!(mmr.peaks().len(), 3);
+
+        let leaf = |i: usize| Tagged::hash_leaf(data[i].as_bytes());
+        let pair = |lower: &CryptoHash, higher: &CryptoHash| RawBytes::combine::<Tagged>(higher, lower);
+        let four = pair(&pair(&leaf(0), &leaf(1)), &pair(&leaf(2), &leaf(3)));
+        let two = pair(&leaf(4), &leaf(5));
+        let root = pair(&four, &pair(&two, &leaf(6)));
+        assert_eq!(mmr.root::<Tagged, RawBytes>().unwrap().data, root.data);
+
+        assert!(MerkleMountainRange::<String>::new().root::<Tagged, RawBytes>().is_none());
+    }
+
+    #[test]
+    fn proves_every_leaf_at_every_size(){
+        let data = data(13);
+        let mmr = MerkleMountainRange::from_data::<Tagged, RawBytes>(&data);
+        for size in 1..=data.len() {
+            let root = mmr.root_at::<Tagged, RawBytes>(size).unwrap();
+            assert_eq!(root.data, MerkleMountainRange::from_data::<Tagged, RawBytes>(&data[..size]).root::<Tagged, RawBytes>().unwrap().data);
+            for (index, leaf) in data[..size].iter().enumerate() {
+                let proof = mmr.prove_at(index, size).unwrap();
+                assert_eq!(proof.verify::<Tagged, RawBytes, _>(leaf, index, &root), Ok(()), "{} of {}", index, size);
+            }
+        }
+
+        let proof = mmr.prove(5).unwrap();
+        let root = mmr.root::<Tagged, RawBytes>().unwrap();
+        assert_eq!(proof.verify::<Tagged, RawBytes, _>(&data[4], 5, &root), Err(VerificationError::WrongRoot));
+        assert_eq!(proof.verify::<Tagged, RawBytes, _>(&data[5], 4, &root), Err(VerificationError::WrongIndex));
+        assert!(mmr.prove_at(5, 5).is_err());
+        assert!(mmr.prove_at(0, 14).is_err());
+    }
+
+    #[test]
+    fn proves_consistency(){
+        let data = data(13);
+        for new_size in 1..=data.len() {
+            let mmr = MerkleMountainRange::from_data::<Tagged, RawBytes>(&data[..new_size]);
+            let new_root = mmr.root::<Tagged, RawBytes>().unwrap();
+            for old_size in 1..=new_size {
+                let old_root = mmr.root_at::<Tagged, RawBytes>(old_size).unwrap();
+                let proof = mmr.consistency_proof(old_size).unwrap();
+                assert_eq!(proof.verify::<Tagged, RawBytes>(&old_root, &new_root), Ok(()), "from {} to {}", old_size, new_size);
+            }
+        }
+
+        let mut rewritten = data.clone();
+        rewritten[1] = "rewritten".to_string();
+        let old = MerkleMountainRange::from_data::<Tagged, RawBytes>(&data[..6]);
+        let mmr = MerkleMountainRange::from_data::<Tagged, RawBytes>(&rewritten);
+        let proof = mmr.consistency_proof(6).unwrap();
+        assert_eq!(proof.verify::<Tagged, RawBytes>(&old.root::<Tagged, RawBytes>().unwrap(), &mmr.root::<Tagged, RawBytes>().unwrap()), Err(VerificationError::WrongOldRoot));
+    }
+}
@@ -1,6 +1,28 @@
 pub mod merkle_tree;
+pub mod combiner;
 pub mod merkle_trace;
+pub mod inclusion_proof;
+pub mod multi_trace;
+pub mod incremental;
+pub mod consistency_proof;
+pub mod rfc6962;
+pub mod bitcoin;
+pub mod sparse;
+pub mod sorted;
+pub mod mmr;
+pub mod patricia;
+pub mod kary;
+pub mod diff;
+pub mod builder;
+pub mod parallel;
+pub mod persist;
+pub mod store;
 pub(super) mod node;
+pub(super) mod walk;
+pub(super) mod peaks;
+#[cfg(test)]
+mod testing;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TreeShape{
     FullCopyExtend,
     FullNullExtend,
@@ -8,3 +30,78 @@ pub enum TreeShape{
     PartialNullExtend,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MerkleError{
+    /// The requested leaf is not one of the leaves the tree was built from
+    IndexOutOfBounds { index: usize, len: usize },
+    /// The requested size is bigger than the tree, or smaller than another size it is compared to
+    SizeOutOfBounds { size: usize, len: usize },
+    /// The requested level is above the root, which is at `depth`
+    LevelOutOfBounds { level: usize, depth: usize },
+    /// The operation can not be done on trees of this shape
+    UnsupportedShape(TreeShape),
+    /// Keys of sparse trees are 256 bits long, this one has `len` bytes
+    InvalidKey { len: usize },
+    /// The value is in the tree at `index`, so its absence can't be proven
+    Present { index: usize },
+    /// The trees do not have the same shape and amount of leaves, so their nodes can't be compared
+    MismatchedTrees,
+    /// There is no such version of the tree, or it was pruned
+    UnknownVersion { version: usize },
+    /// The file the tree was opened from could not be read
+    Io(std::io::ErrorKind),
+    /// The tree is read from a file as it is used, it has to be loaded to change it
+    FileBacked,
+}
+
+/// Why a proof failed to check against a trusted root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationError{
+    /// The leaf at the bottom of the proof is not the hash of the given value
+    WrongLeaf,
+    /// The hash of a node does not match what its children hash to.
+    /// 
+    /// Levels are counted from the leaves, the parent of the leaf is level 1
+    WrongSibling { level: usize },
+    /// The proof is consistent, but it leads to a different root
+    WrongRoot,
+    /// The path of the proof does not lead to the given index
+    WrongIndex,
+    /// A consistency proof does not lead to the old root
+    WrongOldRoot,
+    /// The proof does not have the amount of hashes its sizes require
+    MalformedProof,
+    /// The neighbours of an exclusion proof are not sorted around the value
+    WrongOrder,
+    /// A node of the proof is not the one its parent points to, nodes are counted from the root
+    WrongNode { depth: usize },
+}
+
+/// Why bytes or text could not be read back as a proof
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError{
+    /// The text is not something the `Digester` produces
+    InvalidText,
+    UnsupportedVersion(u8),
+    UnknownShape(u8),
+    UnknownSide(u8),
+    /// The input ended before the proof did
+    Truncated,
+    /// There are bytes left after the proof
+    TrailingBytes,
+    /// The bytes are not the canonical RLP of anything
+    InvalidRlp,
+    /// RLP lists nested deeper than `rlp::MAX_DEPTH`
+    TooDeep,
+    /// More leaves than a tree can have on this platform
+    TooManyLeaves(u64),
+}
+
+/// Why a proof could not be written as bytes or text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError{
+    /// More siblings than the byte that counts them holds
+    TooManySiblings(usize),
+    /// A hash longer than the 255 bytes its length byte holds
+    HashTooLong(usize),
+}
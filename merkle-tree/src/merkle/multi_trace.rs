@@ -0,0 +1,137 @@
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable};
+
+use super::{node::Node, combiner::NodeCombiner, TreeShape, VerificationError};
+
+/// Proof for several leaves of the same tree.
+///
+/// Nodes that are shared by the paths of more than one leaf, or that can be
+/// computed from the leaves, are not included. The siblings are ordered as a
+/// depth first walk of the tree that visits the lower indices first.
+#[derive(Clone)]
+pub struct MultiTrace{
+    pub(crate) indices: Vec<usize>,
+    pub(crate) len: usize,
+    pub(crate) shape: TreeShape,
+    pub(crate) siblings: Vec<CryptoHash>,
+}
+
+impl MultiTrace {
+    /// Indices of the proven leaves, sorted and without repetitions
+    pub fn indices(&self) -> &[usize]{
+        &self.indices
+    }
+
+    /// Amount of leaves of the tree the trace was taken from
+    pub fn len(&self) -> usize{
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.len == 0
+    }
+
+    pub fn shape(&self) -> TreeShape{
+        self.shape
+    }
+
+    pub fn siblings(&self) -> &[CryptoHash]{
+        &self.siblings
+    }
+
+    /// Check that every `(index, leaf)` pair is in the tree whose root is `root`.
+    ///
+    /// The leaves must be exactly the ones the trace was made for, in any order.
+    /// `H` and `C` must be the same ones the tree was built with.
+    pub fn verify<H: CryptoHasher, C: NodeCombiner, V: Hashable + ?Sized>(&self, leaves: &[(usize, &V)], root: &CryptoHash) -> Result<(), VerificationError>{
+        let mut hashes: Vec<(usize, CryptoHash)> = leaves.iter()
+            .map(|(index, leaf)| (*index, Node::hash_leaf::<H, _>(*leaf)))
+            .collect();
+        hashes.sort_by_key(|(index, _)| *index);
+
+        let indices: Vec<usize> = hashes.iter().map(|(index, _)| *index).collect();
+        if indices != self.indices {
+            return Err(VerificationError::WrongIndex);
+        }
+
+        let mut siblings = self.siblings.iter();
+        let computed = Self::rebuild::<H,C>(&hashes, 0, self.len.next_power_of_two(), &mut siblings)?;
+        if siblings.next().is_some() {
+            return Err(VerificationError::WrongIndex);
+        }
+
+        if computed != *root {
+            return Err(VerificationError::WrongRoot);
+        }
+
+        Ok(())
+    }
+
+    /// Mirror of the walk that collected the siblings
+    fn rebuild<'a, H: CryptoHasher, C: NodeCombiner>(
+        leaves: &[(usize, CryptoHash)],
+        left: usize,
+        rigth: usize,
+        siblings: &mut impl Iterator<Item = &'a CryptoHash>
+    ) -> Result<CryptoHash, VerificationError>{
+        if leaves.is_empty() {
+            return siblings.next().cloned().ok_or(VerificationError::WrongIndex);
+        }
+        if rigth - left == 1 {
+            return Ok(leaves[0].1.clone());
+        }
+
+        let mid = (left + rigth) / 2;
+        let split = leaves.partition_point(|(i, _)| *i < mid);
+        let right = Self::rebuild::<H,C>(&leaves[..split], left, mid, siblings)?;
+        let left = Self::rebuild::<H,C>(&leaves[split..], mid, rigth, siblings)?;
+
+        Ok(C::combine::<H>(&left, &right))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, encoding::hex::Hex, merkle::merkle_tree::MerkleTree};
+
+    use crate::merkle::testing::{data, shapes};
+    use super::*;
+
+    #[test]
+    fn verifies_subsets(){
+        let data = data(7);
+        for shape in shapes() {
+            let tree = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+            for mask in 1u32..(1 << data.len()) {
+                let which: Vec<usize> = (0..data.len()).filter(|i| mask & (1 << i) != 0).collect();
+                let trace = tree.generate_multi_trace(&which).unwrap();
+                let leaves: Vec<(usize, &String)> = which.iter().rev().map(|i| (*i, &data[*i])).collect();
+                assert_eq!(trace.verify::<SHA256, Hex, _>(&leaves, tree.root()), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn shares_siblings(){
+        let data = data(16);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::FullNullExtend);
+        // The first 4 leaves are a whole subtree, only the other 2 quarters are needed
+        let trace = tree.generate_multi_trace(&[3, 0, 2, 1, 1]).unwrap();
+        assert_eq!(trace.indices(), &[0, 1, 2, 3]);
+        assert_eq!(trace.siblings().len(), 2);
+
+        let trace = tree.generate_multi_trace(&[0, 15]).unwrap();
+        assert_eq!(trace.siblings().len(), 6);
+    }
+
+    #[test]
+    fn rejects_wrong_leaves(){
+        let data = data(5);
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data, TreeShape::PartialCopyExtend);
+        let trace = tree.generate_multi_trace(&[1, 4]).unwrap();
+
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&[(1, &data[1]), (4, &data[3])], tree.root()), Err(VerificationError::WrongRoot));
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&[(1, &data[1]), (3, &data[3])], tree.root()), Err(VerificationError::WrongIndex));
+        assert_eq!(trace.verify::<SHA256, Hex, _>(&[(1, &data[1])], tree.root()), Err(VerificationError::WrongIndex));
+        assert!(tree.generate_multi_trace(&[1, 5]).is_err());
+    }
+}
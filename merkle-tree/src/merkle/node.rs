@@ -1,9 +1,32 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::hashers::CryptoHash;
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable};
 
+/// Nodes of the path of a trace, trees store only the hashes.
+/// `Arc` lets traces be sent and shared between threads
+#[derive(Clone)]
 pub(crate) struct Node {
     pub(crate) hash: CryptoHash,
-    pub(crate) right: Option<Rc<Node>>,
-    pub(crate) left: Option<Rc<Node>>,
-}
\ No newline at end of file
+    pub(crate) right: Option<Arc<Node>>,
+    pub(crate) left: Option<Arc<Node>>,
+}
+
+impl Node {
+    pub(crate) fn leaf(hash: CryptoHash) -> Self{
+        Self { hash, right: None, left: None }
+    }
+
+    pub(crate) fn is_leaf(&self) -> bool{
+        //By construction, is either of those is none, the other is also
+        self.right.is_none() && self.left.is_none()
+    }
+
+    pub(crate) fn hash_leaf<H: CryptoHasher, V: Hashable + ?Sized>(value: &V) -> CryptoHash{
+        H::hash_leaf(value.to_bits())
+    }
+
+    /// Leaf the null extensions pad with
+    pub(crate) fn null<H: CryptoHasher>() -> CryptoHash{
+        H::hash_leaf(&[0u8;256])
+    }
+}
@@ -0,0 +1,112 @@
+use std::{thread, num::NonZeroUsize};
+
+use crate::hashers::{CryptoHasher, Hashable, digest::HashOutput};
+
+use super::{merkle_tree::MerkleTree, combiner::NodeCombiner, TreeShape};
+
+/// Below this many hashes per thread, spawning costs more than it saves
+const MIN_PER_THREAD: usize = 1024;
+
+impl<T: Hashable + Sync> MerkleTree<T> {
+    /// Same tree as `from_data`, with the hashing split across `threads` threads.
+    ///
+    /// The leaves, and then every level, are hashed by chunks of positions in
+    /// parallel. With `threads` as 0 it uses as many as
+    /// `std::thread::available_parallelism` says.
+    /// Panics if `data` is empty, like `from_data`.
+    pub fn from_data_parallel<H: CryptoHasher, C: NodeCombiner>(data: &[T], tree_shape: TreeShape, threads: usize) -> Self{
+        Self::build_parallel::<H, C>(data, tree_shape, threads)
+    }
+
+    /// Same tree as `from_data_parallel`, with every hash kept as the `Output` of `H`
+    pub fn from_data_parallel_digest<H: CryptoHasher, C: NodeCombiner>(data: &[T], tree_shape: TreeShape, threads: usize) -> MerkleTree<T, H::Output>{
+        MerkleTree::<T, H::Output>::build_parallel::<H, C>(data, tree_shape, threads)
+    }
+}
+
+impl<T: Hashable + Sync, D: HashOutput> MerkleTree<T, D> {
+    fn build_parallel<H: CryptoHasher, C: NodeCombiner>(data: &[T], tree_shape: TreeShape, threads: usize) -> Self{
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            threads => threads,
+        };
+
+        let leaves = par_map(data.len(), threads, |index| D::leaf::<H>(data[index].to_bits()));
+        Self::from_leaves::<H, C, _>(leaves, tree_shape, |width, hash| par_map(width, threads, hash))
+    }
+}
+
+/// `f` of every position in [0, len), in order, computed by chunks on up to `threads` threads
+fn par_map<R: Send, F: Fn(usize) -> R + Sync>(len: usize, threads: usize, f: F) -> Vec<R>{
+    let threads = threads.min(len / MIN_PER_THREAD).max(1);
+    if threads == 1 {
+        return (0..len).map(f).collect();
+    }
+
+    let chunk = len.div_ceil(threads);
+    thread::scope(|scope| {
+        let f = &f;
+        let handles: Vec<_> = (0..len).step_by(chunk)
+            .map(|start| scope.spawn(move || (start..(start + chunk).min(len)).map(f).collect::<Vec<R>>()))
+            .collect();
+
+        let mut results = Vec::with_capacity(len);
+        for handle in handles {
+            results.extend(handle.join().unwrap());
+        }
+        results
+    })
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, encoding::hex::Hex, merkle::combiner::RawBytes};
+
+    use crate::merkle::testing::{data, shapes};
+    use super::*;
+
+    #[test]
+    fn same_tree_as_sequential(){
+        let data = data(17);
+        for shape in shapes() {
+            for len in 1..=data.len() {
+                let parallel = MerkleTree::from_data_parallel::<SHA256, Hex>(&data[..len], shape, 4);
+                let mut sequential = MerkleTree::from_data::<SHA256, Hex>(&data[..len], shape);
+                assert_eq!(parallel.root().data, sequential.root().data, "{:?} with {} leaves", shape, len);
+
+                for (i, leaf) in data[..len].iter().enumerate() {
+                    let trace = parallel.generate_trace(i).unwrap();
+                    assert_eq!(trace.verify::<SHA256, Hex, _>(leaf, i, sequential.root()), Ok(()));
+                }
+
+                let mut parallel = parallel;
+                let changed = "changed".to_string();
+                parallel.update::<SHA256, Hex>(0, &changed).unwrap();
+                sequential.update::<SHA256, Hex>(0, &changed).unwrap();
+                assert_eq!(parallel.root().data, sequential.root().data, "{:?} with {} leaves", shape, len);
+            }
+        }
+    }
+
+    #[test]
+    fn splits_big_trees(){
+        // Enough leaves for several chunks per level, and not a power of two
+        let data = data(3 * MIN_PER_THREAD + 5);
+        for shape in shapes() {
+            let sequential = MerkleTree::from_data::<SHA256, RawBytes>(&data, shape);
+            for threads in [0, 1, 2, 3, 8] {
+                let parallel = MerkleTree::from_data_parallel::<SHA256, RawBytes>(&data, shape, threads);
+                assert_eq!(parallel.root().data, sequential.root().data, "{:?} on {} threads", shape, threads);
+            }
+            let digest = MerkleTree::from_data_parallel_digest::<SHA256, RawBytes>(&data, shape, 3);
+            assert_eq!(digest.root().as_ref(), sequential.root().data.as_slice(), "{:?}", shape);
+        }
+    }
+
+    #[test]
+    fn par_map_keeps_order(){
+        let len = 5 * MIN_PER_THREAD + 3;
+        assert_eq!(par_map(len, 4, |i| i * 2), (0..len).map(|i| i * 2).collect::<Vec<usize>>());
+        assert_eq!(par_map(0, 4, |i| i), Vec::<usize>::new());
+    }
+}
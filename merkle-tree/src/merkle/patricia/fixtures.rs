@@ -0,0 +1,159 @@
+//! Trie tests of ethereum/tests, from `TrieTests/trieanyorder.json` and `TrieTests/trietest.json`
+
+use crate::{hashers::CryptoHash, encoding::{Digester, hex::Hex}};
+
+use super::PatriciaTrie;
+
+/// Updates to an empty trie, `None` deletes the key, and the root they end with
+pub(super) struct Fixture{
+    pub(super) name: String,
+    pub(super) updates: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    pub(super) root: CryptoHash,
+}
+
+impl Fixture {
+    pub(super) fn trie(&self) -> PatriciaTrie{
+        let mut trie = PatriciaTrie::new();
+        for (key, value) in &self.updates {
+            match value {
+                Some(value) => trie.put(key, value.clone()),
+                None => { trie.delete(key); },
+            }
+        }
+        trie
+    }
+
+    /// What the trie holds after the updates
+    pub(super) fn pairs(&self) -> Vec<(Vec<u8>, Vec<u8>)>{
+        let mut pairs: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        for (key, value) in &self.updates {
+            pairs.retain(|(kept, _)| kept != key);
+            if let Some(value) = value.as_ref().filter(|value| !value.is_empty()) {
+                pairs.push((key.clone(), value.clone()));
+            }
+        }
+        pairs
+    }
+}
+
+pub(super) fn fixtures() -> Vec<Fixture>{
+    [include_str!("fixtures/trieanyorder.json"), include_str!("fixtures/trietest.json")]
+        .into_iter()
+        .flat_map(parse)
+        .collect()
+}
+
+/// The tests of a file, whose `in` is an object in the first one and a list of pairs in the second
+fn parse(file: &str) -> Vec<Fixture>{
+    let Json::Object(tests) = Parser { rest: file }.value() else {
+        panic!("The file is not an object of tests");
+    };
+
+    tests.into_iter().map(|(name, test)| {
+        let Json::Object(fields) = test else {
+            panic!("{} is not an object", name);
+        };
+        let field = |wanted: &str| fields.iter().find(|(field, _)| field == wanted).map(|(_, value)| value).unwrap();
+
+        let updates = match field("in") {
+            Json::Object(pairs) => pairs.iter().map(|(key, value)| (bytes(key), value.bytes())).collect(),
+            Json::List(pairs) => pairs.iter().map(|pair| match pair {
+                Json::List(pair) => (pair[0].bytes().unwrap(), pair[1].bytes()),
+                _ => panic!("{} has an update that is not a pair", name),
+            }).collect(),
+            _ => panic!("{} has no updates", name),
+        };
+        let root = CryptoHash::from(field("root").bytes().unwrap());
+        Fixture { name, updates, root }
+    }).collect()
+}
+
+/// The strings of the tests are hex if they start with `0x`, and bytes as they are otherwise
+fn bytes(text: &str) -> Vec<u8>{
+    match text.strip_prefix("0x") {
+        Some(hex) => Hex::undigest(hex).unwrap(),
+        None => text.as_bytes().to_vec(),
+    }
+}
+
+/// The part of JSON the tests use, strings are never escaped
+enum Json{
+    Null,
+    Text(String),
+    List(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn bytes(&self) -> Option<Vec<u8>>{
+        match self {
+            Json::Null => None,
+            Json::Text(text) => Some(bytes(text)),
+            _ => panic!("Expected a string or null"),
+        }
+    }
+}
+
+struct Parser<'a>{
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Json{
+        self.rest = self.rest.trim_start();
+        if let Some(rest) = self.rest.strip_prefix("null") {
+            self.rest = rest;
+            return Json::Null;
+        }
+
+        match self.next() {
+            '"' => Json::Text(self.text()),
+            '[' => Json::List(self.items(']', Self::value)),
+            '{' => Json::Object(self.items('}', |parser| {
+                parser.expect('"');
+                let key = parser.text();
+                parser.expect(':');
+                (key, parser.value())
+            })),
+            other => panic!("Unexpected {}", other),
+        }
+    }
+
+    /// Items separated by commas up to `end`, the opening bracket already read
+    fn items<T>(&mut self, end: char, mut item: impl FnMut(&mut Self) -> T) -> Vec<T>{
+        let mut items = Vec::new();
+        self.rest = self.rest.trim_start();
+        if let Some(rest) = self.rest.strip_prefix(end) {
+            self.rest = rest;
+            return items;
+        }
+
+        loop {
+            items.push(item(self));
+            match self.next() {
+                ',' => continue,
+                close if close == end => return items,
+                other => panic!("Unexpected {}", other),
+            }
+        }
+    }
+
+    /// Rest of a string whose opening quote was read
+    fn text(&mut self) -> String{
+        let (text, rest) = self.rest.split_once('"').unwrap();
+        assert!(!text.contains('\\'), "Escaped strings are not supported");
+        self.rest = rest;
+        text.to_string()
+    }
+
+    fn next(&mut self) -> char{
+        self.rest = self.rest.trim_start();
+        let next = self.rest.chars().next().unwrap();
+        self.rest = &self.rest[next.len_utf8()..];
+        next
+    }
+
+    fn expect(&mut self, wanted: char){
+        assert_eq!(self.next(), wanted);
+    }
+}
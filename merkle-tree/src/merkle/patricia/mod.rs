@@ -0,0 +1,314 @@
+pub mod rlp;
+pub mod nibbles;
+pub mod proof;
+#[cfg(test)]
+mod fixtures;
+
+use crate::hashers::{CryptoHash, CryptoHasher};
+
+use self::{nibbles::{nibbles, hex_prefix}, rlp::{encode_bytes, encode_list}};
+
+/// Merkle Patricia Trie, the key value map whose roots Ethereum commits to in every block.
+///
+/// Keys are walked one nibble at a time. Branches have a child for every nibble,
+/// extensions skip the nibbles all the keys below them share, and leaves hold the
+/// rest of the key and the value. Every node is hashed as its RLP, unless that is
+/// shorter than a hash, in which case its parent holds it as it is.
+/// Use `Keccak256` as the hasher to get the same roots as Ethereum.
+pub struct PatriciaTrie{
+    root: TrieNode,
+}
+
+#[derive(Clone)]
+enum TrieNode{
+    Empty,
+    Leaf { path: Vec<u8>, value: Vec<u8> },
+    Extension { path: Vec<u8>, child: Box<TrieNode> },
+    Branch { children: Box<[TrieNode; 16]>, value: Option<Vec<u8>> },
+}
+
+impl PatriciaTrie {
+    pub fn new() -> Self{
+        Self { root: TrieNode::Empty }
+    }
+
+    pub fn is_empty(&self) -> bool{
+        matches!(self.root, TrieNode::Empty)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]>{
+        let path = nibbles(key);
+        let mut path = path.as_slice();
+        let mut node = &self.root;
+        loop {
+            match node {
+                TrieNode::Empty => return None,
+                TrieNode::Leaf { path: rest, value } => return (rest == path).then_some(value.as_slice()),
+                TrieNode::Extension { path: skipped, child } => {
+                    path = path.strip_prefix(skipped.as_slice())?;
+                    node = child;
+                },
+                TrieNode::Branch { children, value } => match path.split_first() {
+                    None => return value.as_deref(),
+                    Some((nibble, rest)) => {
+                        node = &children[*nibble as usize];
+                        path = rest;
+                    },
+                },
+            }
+        }
+    }
+
+    /// Set the value of `key`, an empty value deletes it like in Ethereum
+    pub fn put(&mut self, key: &[u8], value: Vec<u8>){
+        if value.is_empty() {
+            self.delete(key);
+            return;
+        }
+
+        let root = std::mem::replace(&mut self.root, TrieNode::Empty);
+        self.root = root.insert(&nibbles(key), value);
+    }
+
+    /// Remove `key`, returning the value it had
+    pub fn delete(&mut self, key: &[u8]) -> Option<Vec<u8>>{
+        let root = std::mem::replace(&mut self.root, TrieNode::Empty);
+        let (root, old) = root.remove(&nibbles(key));
+        self.root = root;
+        old
+    }
+
+    /// Hash of the RLP of the root, even if it is shorter than a hash
+    pub fn root<H: CryptoHasher>(&self) -> CryptoHash{
+        H::hash(&self.root.encode::<H>())
+    }
+
+    /// RLP of the nodes on the way to `key`, from the root, in the format of `eth_getProof`.
+    ///
+    /// Nodes held by their parent are not repeated. If `key` is not in the trie,
+    /// the proof ends at the node where its path leaves the trie, which proves it is absent.
+    pub fn prove<H: CryptoHasher>(&self, key: &[u8]) -> Vec<Vec<u8>>{
+        let path = nibbles(key);
+        let mut path = path.as_slice();
+        let mut node = &self.root;
+        let mut proof = vec![node.encode::<H>()];
+        loop {
+            let child = match node {
+                TrieNode::Empty | TrieNode::Leaf { .. } => break,
+                TrieNode::Extension { path: skipped, child } => match path.strip_prefix(skipped.as_slice()) {
+                    Some(rest) => {
+                        path = rest;
+                        child
+                    },
+                    None => break,
+                },
+                TrieNode::Branch { children, .. } => match path.split_first() {
+                    Some((nibble, rest)) => {
+                        path = rest;
+                        &children[*nibble as usize]
+                    },
+                    None => break,
+                },
+            };
+
+            let encoded = child.encode::<H>();
+            if encoded.len() >= 32 {
+                proof.push(encoded);
+            }
+            node = child;
+        }
+
+        proof
+    }
+}
+
+impl Default for PatriciaTrie {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TrieNode {
+    fn empty_children() -> Box<[TrieNode; 16]>{
+        Box::new(std::array::from_fn(|_| TrieNode::Empty))
+    }
+
+    fn insert(self, path: &[u8], value: Vec<u8>) -> Self{
+        match self {
+            TrieNode::Empty => TrieNode::Leaf { path: path.to_vec(), value },
+            TrieNode::Leaf { path: existing, value: old } => {
+                if existing == path {
+                    return TrieNode::Leaf { path: existing, value };
+                }
+
+                let shared = shared_prefix(&existing, path);
+                let branch = TrieNode::Branch { children: Self::empty_children(), value: None }
+                    .insert(&existing[shared..], old)
+                    .insert(&path[shared..], value);
+                Self::extend(&path[..shared], branch)
+            },
+            TrieNode::Extension { path: skipped, child } => {
+                let shared = shared_prefix(&skipped, path);
+                if shared == skipped.len() {
+                    return TrieNode::Extension { path: skipped, child: Box::new(child.insert(&path[shared..], value)) };
+                }
+
+                // The extension is split where the paths diverge
+                let mut children = Self::empty_children();
+                children[skipped[shared] as usize] = Self::extend(&skipped[shared + 1..], *child);
+                let branch = TrieNode::Branch { children, value: None }.insert(&path[shared..], value);
+                Self::extend(&path[..shared], branch)
+            },
+            TrieNode::Branch { mut children, value: old } => match path.split_first() {
+                None => TrieNode::Branch { children, value: Some(value) },
+                Some((nibble, rest)) => {
+                    let child = std::mem::replace(&mut children[*nibble as usize], TrieNode::Empty);
+                    children[*nibble as usize] = child.insert(rest, value);
+                    TrieNode::Branch { children, value: old }
+                },
+            },
+        }
+    }
+
+    /// The node without `path`, and the value it had. Nodes left with a single child are merged into it
+    fn remove(self, path: &[u8]) -> (Self, Option<Vec<u8>>){
+        match self {
+            TrieNode::Empty => (TrieNode::Empty, None),
+            TrieNode::Leaf { path: existing, value } => {
+                if existing == path {
+                    (TrieNode::Empty, Some(value))
+                }else{
+                    (TrieNode::Leaf { path: existing, value }, None)
+                }
+            },
+            TrieNode::Extension { path: skipped, child } => {
+                let Some(rest) = path.strip_prefix(skipped.as_slice()) else {
+                    return (TrieNode::Extension { path: skipped, child }, None);
+                };
+
+                let (child, old) = child.remove(rest);
+                (Self::extend(&skipped, child), old)
+            },
+            TrieNode::Branch { mut children, value } => {
+                let (value, old) = match path.split_first() {
+                    None => (None, value),
+                    Some((nibble, rest)) => {
+                        let child = std::mem::replace(&mut children[*nibble as usize], TrieNode::Empty);
+                        let (child, old) = child.remove(rest);
+                        children[*nibble as usize] = child;
+                        (value, old)
+                    },
+                };
+
+                (Self::collapse(children, value), old)
+            },
+        }
+    }
+
+    /// `node` below the nibbles of `path`, merged with it if it is not a branch
+    fn extend(path: &[u8], node: TrieNode) -> Self{
+        if path.is_empty() {
+            return node;
+        }
+
+        match node {
+            TrieNode::Empty => TrieNode::Empty,
+            TrieNode::Leaf { path: rest, value } => TrieNode::Leaf { path: [path, &rest].concat(), value },
+            TrieNode::Extension { path: rest, child } => TrieNode::Extension { path: [path, &rest].concat(), child },
+            branch => TrieNode::Extension { path: path.to_vec(), child: Box::new(branch) },
+        }
+    }
+
+    /// A branch with less than two things in it is replaced by the only one it has
+    fn collapse(mut children: Box<[TrieNode; 16]>, value: Option<Vec<u8>>) -> Self{
+        let used: Vec<usize> = (0..16).filter(|i| !matches!(children[*i], TrieNode::Empty)).collect();
+        match (used.as_slice(), value) {
+            ([], None) => TrieNode::Empty,
+            ([], Some(value)) => TrieNode::Leaf { path: Vec::new(), value },
+            ([only], None) => {
+                let child = std::mem::replace(&mut children[*only], TrieNode::Empty);
+                Self::extend(&[*only as u8], child)
+            },
+            (_, value) => TrieNode::Branch { children, value },
+        }
+    }
+
+    fn encode<H: CryptoHasher>(&self) -> Vec<u8>{
+        match self {
+            TrieNode::Empty => encode_bytes(&[]),
+            TrieNode::Leaf { path, value } => encode_list(&[encode_bytes(&hex_prefix(path, true)), encode_bytes(value)]),
+            TrieNode::Extension { path, child } => encode_list(&[encode_bytes(&hex_prefix(path, false)), child.reference::<H>()]),
+            TrieNode::Branch { children, value } => {
+                let mut items: Vec<Vec<u8>> = children.iter().map(TrieNode::reference::<H>).collect();
+                items.push(encode_bytes(value.as_deref().unwrap_or_default()));
+                encode_list(&items)
+            },
+        }
+    }
+
+    /// What the parent holds, the node itself if its RLP is shorter than a hash or its hash otherwise
+    fn reference<H: CryptoHasher>(&self) -> Vec<u8>{
+        let encoded = self.encode::<H>();
+        if encoded.len() < 32 {
+            return encoded;
+        }
+
+        encode_bytes(&H::hash(&encoded).data)
+    }
+}
+
+fn shared_prefix(a: &[u8], b: &[u8]) -> usize{
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::keccak256::Keccak256, encoding::{Digestable, hex::Hex}};
+
+    use super::*;
+
+    fn root(trie: &PatriciaTrie) -> String{
+        trie.root::<Keccak256>().digest::<Hex>().to_lowercase()
+    }
+
+    #[test]
+    fn official_fixtures(){
+        assert_eq!(root(&PatriciaTrie::new()), "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421");
+        for fixture in fixtures::fixtures() {
+            assert_eq!(fixture.trie().root::<Keccak256>(), fixture.root, "{}", fixture.name);
+        }
+    }
+
+    #[test]
+    fn get_put_delete(){
+        let pairs: Vec<(String, String)> = (0..200).map(|i| (format!("key {}", i * 7919 % 1000), format!("value {}", i))).collect();
+        let mut trie = PatriciaTrie::new();
+        for (key, value) in &pairs {
+            trie.put(key.as_bytes(), value.as_bytes().to_vec());
+        }
+
+        for (key, value) in &pairs {
+            assert_eq!(trie.get(key.as_bytes()), Some(value.as_bytes()));
+        }
+        assert_eq!(trie.get(b"key"), None);
+        assert_eq!(trie.get(b"key 1000"), None);
+
+        // Deleting half leaves the same trie as never inserting it
+        let mut half = PatriciaTrie::new();
+        for (i, (key, value)) in pairs.iter().enumerate() {
+            if i % 2 == 0 {
+                half.put(key.as_bytes(), value.as_bytes().to_vec());
+            }else{
+                assert_eq!(trie.delete(key.as_bytes()), Some(value.as_bytes().to_vec()));
+            }
+        }
+        assert_eq!(trie.delete(b"key"), None);
+        assert_eq!(root(&trie), root(&half));
+
+        for (key, _) in pairs.iter().step_by(2) {
+            trie.delete(key.as_bytes());
+        }
+        assert!(trie.is_empty());
+        assert_eq!(root(&trie), root(&PatriciaTrie::new()));
+    }
+}
@@ -0,0 +1,58 @@
+use crate::merkle::DecodeError;
+
+/// Half bytes of `key`, the most significant one first
+pub fn nibbles(key: &[u8]) -> Vec<u8>{
+    key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect()
+}
+
+/// Hex prefix encoding of a path, which packs the nibbles back into bytes.
+///
+/// The first nibble flags if the path ends in a leaf and if its length is odd,
+/// in which case the first nibble of the path goes right after it.
+pub fn hex_prefix(path: &[u8], leaf: bool) -> Vec<u8>{
+    let flag = if leaf { 2 } else { 0 };
+    let mut encoded = Vec::with_capacity(path.len() / 2 + 1);
+    let rest = if path.len() % 2 == 1 {
+        encoded.push(((flag + 1) << 4) | path[0]);
+        &path[1..]
+    }else{
+        encoded.push(flag << 4);
+        path
+    };
+
+    encoded.extend(rest.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    encoded
+}
+
+/// Inverse of `hex_prefix`, the nibbles of the path and if it ends in a leaf
+pub fn from_hex_prefix(encoded: &[u8]) -> Result<(Vec<u8>, bool), DecodeError>{
+    let first = *encoded.first().ok_or(DecodeError::Truncated)?;
+    let flag = first >> 4;
+    if flag > 3 || (flag % 2 == 0 && first & 0x0f != 0) {
+        return Err(DecodeError::InvalidRlp);
+    }
+
+    let mut path = if flag % 2 == 1 { vec![first & 0x0f] } else { Vec::new() };
+    path.extend(nibbles(&encoded[1..]));
+    Ok((path, flag >= 2))
+}
+
+#[cfg(test)]
+mod test{
+    use super::*;
+
+    #[test]
+    fn hex_prefix_round_trips(){
+        // The examples of the yellow paper
+        assert_eq!(hex_prefix(&[1, 2, 3, 4, 5], false), vec![0x11, 0x23, 0x45]);
+        assert_eq!(hex_prefix(&[0, 1, 2, 3, 4, 5], false), vec![0x00, 0x01, 0x23, 0x45]);
+        assert_eq!(hex_prefix(&[0, 15, 1, 12, 11, 8], true), vec![0x20, 0x0f, 0x1c, 0xb8]);
+        assert_eq!(hex_prefix(&[15, 1, 12, 11, 8], true), vec![0x3f, 0x1c, 0xb8]);
+
+        for (path, leaf) in [(vec![], true), (vec![7], false), (nibbles(b"dog"), true)] {
+            assert_eq!(from_hex_prefix(&hex_prefix(&path, leaf)), Ok((path, leaf)));
+        }
+        assert!(from_hex_prefix(&[0x45]).is_err());
+        assert!(from_hex_prefix(&[0x01]).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{hashers::{CryptoHash, CryptoHasher}, encoding::{Digester, hex::Hex}, merkle::{VerificationError, DecodeError}};
+
+use super::{nibbles::{nibbles, from_hex_prefix}, rlp::{Rlp, encode_bytes, encode_list, encode_uint, decode_uint}};
+
+/// Check a proof made by `PatriciaTrie::prove`, or by `eth_getProof`, against a trusted root.
+///
+/// Returns the value of `key`, or `None` if the proof shows it is not in the trie.
+/// `H` must be the same hasher the trie was built with, `Keccak256` for Ethereum.
+pub fn verify_proof<H: CryptoHasher>(root: &CryptoHash, key: &[u8], proof: &[Vec<u8>]) -> Result<Option<Vec<u8>>, VerificationError>{
+    if proof.is_empty() {
+        // Some clients send nothing at all for an empty trie
+        if *root == H::hash(&encode_bytes(&[])) {
+            return Ok(None);
+        }
+        return Err(VerificationError::MalformedProof);
+    }
+    // Every node is checked against the reference to it before it is decoded
+    if H::hash(&proof[0]) != *root {
+        return Err(VerificationError::WrongRoot);
+    }
+
+    let path = nibbles(key);
+    let mut path = path.as_slice();
+    let mut used = 1;
+    let mut node = decode_node(&proof[0])?;
+    let value = loop {
+        let mut items = match node {
+            Rlp::Bytes([]) => break None,
+            Rlp::Bytes(_) => return Err(VerificationError::MalformedProof),
+            Rlp::List(items) => items,
+        };
+
+        let child = match items.len() {
+            2 => {
+                let encoded_path = items[0].as_bytes().ok_or(VerificationError::MalformedProof)?;
+                let (skipped, leaf) = from_hex_prefix(encoded_path).map_err(|_| VerificationError::MalformedProof)?;
+                if leaf {
+                    let value = items[1].as_bytes().ok_or(VerificationError::MalformedProof)?;
+                    break (skipped == path).then(|| value.to_vec());
+                }
+
+                match path.strip_prefix(skipped.as_slice()) {
+                    Some(rest) => {
+                        path = rest;
+                        items.swap_remove(1)
+                    },
+                    None => break None,
+                }
+            },
+            17 => match path.split_first() {
+                None => {
+                    let value = items[16].as_bytes().ok_or(VerificationError::MalformedProof)?;
+                    break (!value.is_empty()).then(|| value.to_vec());
+                },
+                Some((nibble, rest)) => {
+                    path = rest;
+                    items.swap_remove(*nibble as usize)
+                },
+            },
+            _ => return Err(VerificationError::MalformedProof),
+        };
+
+        node = match child {
+            Rlp::Bytes([]) => break None,
+            Rlp::Bytes(hash) => {
+                let next = proof.get(used).ok_or(VerificationError::MalformedProof)?;
+                if H::hash(next) != CryptoHash::from(hash) {
+                    return Err(VerificationError::WrongNode { depth: used });
+                }
+                used += 1;
+                decode_node(next)?
+            },
+            embedded => embedded,
+        };
+    };
+
+    if used != proof.len() {
+        return Err(VerificationError::MalformedProof);
+    }
+
+    Ok(value)
+}
+
+fn decode_node(node: &[u8]) -> Result<Rlp<'_>, VerificationError>{
+    Rlp::decode(node).map_err(|_| VerificationError::MalformedProof)
+}
+
+/// Account of the Ethereum state trie
+#[derive(Clone)]
+pub struct Account{
+    nonce: u64,
+    balance: u128,
+    storage_root: CryptoHash,
+    code_hash: CryptoHash,
+}
+
+impl Account {
+    pub fn new(nonce: u64, balance: u128, storage_root: CryptoHash, code_hash: CryptoHash) -> Self{
+        Self { nonce, balance, storage_root, code_hash }
+    }
+
+    pub fn nonce(&self) -> u64{
+        self.nonce
+    }
+
+    /// In wei
+    pub fn balance(&self) -> u128{
+        self.balance
+    }
+
+    /// Root of the storage trie, which storage proofs are checked against
+    pub fn storage_root(&self) -> &CryptoHash{
+        &self.storage_root
+    }
+
+    pub fn code_hash(&self) -> &CryptoHash{
+        &self.code_hash
+    }
+
+    /// The value the state trie holds, `[nonce, balance, storage_root, code_hash]`
+    pub fn to_rlp(&self) -> Vec<u8>{
+        encode_list(&[
+            encode_bytes(&encode_uint(self.nonce as u128)),
+            encode_bytes(&encode_uint(self.balance)),
+            encode_bytes(&self.storage_root.data),
+            encode_bytes(&self.code_hash.data),
+        ])
+    }
+
+    pub fn from_rlp(bytes: &[u8]) -> Result<Self, DecodeError>{
+        let item = Rlp::decode(bytes)?;
+        let fields: Vec<&[u8]> = item.as_list()
+            .ok_or(DecodeError::InvalidRlp)?
+            .iter()
+            .map(|field| field.as_bytes().ok_or(DecodeError::InvalidRlp))
+            .collect::<Result<_, _>>()?;
+        let [nonce, balance, storage_root, code_hash] = fields.as_slice() else {
+            return Err(DecodeError::InvalidRlp);
+        };
+
+        let nonce = decode_uint(nonce).filter(|nonce| *nonce <= u64::MAX as u128).ok_or(DecodeError::InvalidRlp)?;
+        Ok(Self {
+            nonce: nonce as u64,
+            balance: decode_uint(balance).ok_or(DecodeError::InvalidRlp)?,
+            storage_root: CryptoHash { data: storage_root.to_vec() },
+            code_hash: CryptoHash { data: code_hash.to_vec() },
+        })
+    }
+}
+
+/// Check the `accountProof` of `eth_getProof`, the account of `address` or `None` if it does not exist
+pub fn verify_account<H: CryptoHasher>(state_root: &CryptoHash, address: &[u8], proof: &[Vec<u8>]) -> Result<Option<Account>, VerificationError>{
+    let key = H::hash(address);
+    match verify_proof::<H>(state_root, &key.data, proof)? {
+        Some(value) => Account::from_rlp(&value).map(Some).map_err(|_| VerificationError::MalformedProof),
+        None => Ok(None),
+    }
+}
+
+/// Check one of the `storageProof` of `eth_getProof` against the storage root of its account.
+///
+/// Returns the value of the 32 bytes `slot` as big endian bytes without leading zeros, empty if it is zero.
+pub fn verify_storage<H: CryptoHasher>(storage_root: &CryptoHash, slot: &[u8], proof: &[Vec<u8>]) -> Result<Vec<u8>, VerificationError>{
+    let key = H::hash(slot);
+    match verify_proof::<H>(storage_root, &key.data, proof)? {
+        Some(value) => match Rlp::decode(&value) {
+            Ok(Rlp::Bytes(bytes)) => Ok(bytes.to_vec()),
+            _ => Err(VerificationError::MalformedProof),
+        },
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Nodes of a proof as `eth_getProof` returns them, hex with a `0x` prefix
+pub fn proof_from_hex(nodes: &[&str]) -> Result<Vec<Vec<u8>>, DecodeError>{
+    nodes.iter()
+        .map(|node| Hex::undigest(node.strip_prefix("0x").unwrap_or(node)).ok_or(DecodeError::InvalidText))
+        .collect()
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::keccak256::Keccak256, merkle::patricia::{PatriciaTrie, fixtures::fixtures}};
+
+    use super::*;
+
+    /// A state trie with six accounts, the first one has code and three storage slots.
+    /// Made with `PatriciaTrie`, whose roots are checked against upstream ones in `upstream_tries_prove_their_keys`
+    const STATE_ROOT: &str = "c3470e2cab23640971c6aaa756715b277349863a688f258c659928df2339a966";
+    const STORAGE_ROOT: &str = "1337a54e2e3b8c53c2cd3371fc176a73e6942ebe59bf37cea9a563508627fe77";
+    const ADDRESS: &str = "de0b295669a9fd93d5f28d9ec85e40f4cb697bae";
+    const ACCOUNT_PROOF: [&str; 2] = [
+        "0xf8d1a088a9b20ec8fd530e6bbe119a1ebdaf9768ba0219fd65c31d1ad27167d47e219f8080808080a000d45d55b2c07f5ad231af2ce1460160e890fe778a0d43f5f0fa41de5c24bf508080a017c21003ec3204c45c2429fc922c11445678b6fb7af888db73ce37b3f01a21f4a0875fcaad3b61854303044463117176f0f6d0a6d888c664b385d9a03a96653982a081ba0f24a390b13fb327aa68f45c8e7d430331b7ff66a431eb5996c8f33ea7a28080a05c6736d7c6b4fe27aef3010a1ec28c5b52dae4200c2d668cd8b798f0f561a86e8080",
+        "0xf871a039dad69db0f578edc4cad1a5009df28aa8c7f1cfac6e3c95e1549bef16b7ec99b84ef84c01880de0b6b3a7640000a01337a54e2e3b8c53c2cd3371fc176a73e6942ebe59bf37cea9a563508627fe77a0ccb1f717aa77602faf03a594761a36956b1c4cf44c6b336d1db57da799b331b8",
+    ];
+    /// Proof that `0x0909...09` has no account
+    const ABSENT_PROOF: [&str; 2] = [
+        ACCOUNT_PROOF[0],
+        "0xf86ba032c2f498f37adab9c7a4bf0aae161bb929b33867f5b5976848450005f577b8cbb848f84603820bb8a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421a0c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+    ];
+    const SLOT_1_PROOF: [&str; 2] = [
+        "0xf8718080a0f73cea67884580eec8c3f6d0746360906cf897bf812183520e51b89a12166cfe80a04025f53b1cf482f141a575cb5ac55f36dbd11d0c0c13827bc0de3cc8a664e849808080808080a0b0dd17c59d83cca7e3f924f0d35a156b7f8d1aa78e0d04dd547d0d807b5e832d8080808080",
+        "0xe5a0310e2d527612073b26eecdfd717e6a320cf44b4afac2b0732d9fcbe2b7fa0cf683820100",
+    ];
+
+    fn hash(hex: &str) -> CryptoHash{
+        CryptoHash { data: Hex::undigest(hex).unwrap() }
+    }
+
+    fn slot(i: u8) -> [u8; 32]{
+        let mut slot = [0u8; 32];
+        slot[31] = i;
+        slot
+    }
+
+    #[test]
+    fn account_and_storage_fixtures(){
+        let address = Hex::undigest(ADDRESS).unwrap();
+        let account = verify_account::<Keccak256>(&hash(STATE_ROOT), &address, &proof_from_hex(&ACCOUNT_PROOF).unwrap()).unwrap().unwrap();
+        assert_eq!(account.nonce(), 1);
+        assert_eq!(account.balance(), 1_000_000_000_000_000_000);
+        assert_eq!(account.storage_root().data, hash(STORAGE_ROOT).data);
+        assert_eq!(account.code_hash().data, Keccak256::hash(b"42").data);
+
+        let value = verify_storage::<Keccak256>(account.storage_root(), &slot(1), &proof_from_hex(&SLOT_1_PROOF).unwrap());
+        assert_eq!(value, Ok(vec![0x01, 0x00]));
+
+        // The proof of slot 7 is the root, whose branch has no child where its path goes
+        let absent_slot = proof_from_hex(&SLOT_1_PROOF[..1]).unwrap();
+        assert_eq!(verify_storage::<Keccak256>(account.storage_root(), &slot(7), &absent_slot), Ok(Vec::new()));
+
+        let absent = verify_account::<Keccak256>(&hash(STATE_ROOT), &[9; 20], &proof_from_hex(&ABSENT_PROOF).unwrap());
+        assert!(matches!(absent, Ok(None)));
+    }
+
+    #[test]
+    fn upstream_tries_prove_their_keys(){
+        for fixture in fixtures() {
+            let trie = fixture.trie();
+            for (key, value) in fixture.pairs() {
+                let proof = trie.prove::<Keccak256>(&key);
+                assert_eq!(verify_proof::<Keccak256>(&fixture.root, &key, &proof), Ok(Some(value)), "{}", fixture.name);
+            }
+            let proof = trie.prove::<Keccak256>(b"dogs");
+            assert_eq!(verify_proof::<Keccak256>(&fixture.root, b"dogs", &proof), Ok(None), "{}", fixture.name);
+        }
+    }
+
+    #[test]
+    fn every_key_proves(){
+        let mut trie = PatriciaTrie::new();
+        let root = trie.root::<Keccak256>();
+        assert_eq!(verify_proof::<Keccak256>(&root, b"dog", &trie.prove::<Keccak256>(b"dog")), Ok(None));
+        assert_eq!(verify_proof::<Keccak256>(&root, b"dog", &[]), Ok(None));
+
+        // Short keys and values, so most nodes are embedded in their parents
+        let pairs: Vec<(String, String)> = (0..100).map(|i| (format!("k{}", i), format!("v{}", i))).collect();
+        for (key, value) in &pairs {
+            trie.put(key.as_bytes(), value.as_bytes().to_vec());
+        }
+
+        let root = trie.root::<Keccak256>();
+        for (key, value) in &pairs {
+            let proof = trie.prove::<Keccak256>(key.as_bytes());
+            assert_eq!(verify_proof::<Keccak256>(&root, key.as_bytes(), &proof), Ok(Some(value.as_bytes().to_vec())));
+        }
+        for absent in ["k", "k100", "j1", "k1000"] {
+            let proof = trie.prove::<Keccak256>(absent.as_bytes());
+            assert_eq!(verify_proof::<Keccak256>(&root, absent.as_bytes(), &proof), Ok(None), "{}", absent);
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_proofs(){
+        let root = hash(STATE_ROOT);
+        let address = Hex::undigest(ADDRESS).unwrap();
+        let proof = proof_from_hex(&ACCOUNT_PROOF).unwrap();
+
+        let mut tampered = proof.clone();
+        let last = tampered[1].len() - 1;
+        tampered[1][last] ^= 1;
+        assert!(matches!(verify_account::<Keccak256>(&root, &address, &tampered), Err(VerificationError::WrongNode { depth: 1 })));
+
+        assert!(matches!(verify_account::<Keccak256>(&hash(STORAGE_ROOT), &address, &proof), Err(VerificationError::WrongRoot)));
+        // Nodes that are not the ones referenced are never decoded
+        assert!(matches!(verify_account::<Keccak256>(&root, &address, &[vec![0xc1; 64]]), Err(VerificationError::WrongRoot)));
+        assert!(matches!(verify_account::<Keccak256>(&root, &address, &proof[..1]), Err(VerificationError::MalformedProof)));
+
+        let mut extra = proof.clone();
+        extra.push(proof[1].clone());
+        assert!(matches!(verify_account::<Keccak256>(&root, &address, &extra), Err(VerificationError::MalformedProof)));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::merkle::{inclusion_proof::Reader, DecodeError};
+
+/// Deepest nesting of lists `Rlp::decode` accepts, no node of a trie comes close
+pub const MAX_DEPTH: usize = 32;
+
+/// Recursive Length Prefix, the serialization Ethereum uses for every node of its tries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rlp<'a>{
+    Bytes(&'a [u8]),
+    List(Vec<Rlp<'a>>),
+}
+
+impl<'a> Rlp<'a> {
+    /// Read a single item that takes all of `bytes`, only accepting its canonical encoding
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, DecodeError>{
+        let mut reader = Reader { bytes };
+        let item = Self::read(&mut reader, 0)?;
+        if !reader.bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+
+        Ok(item)
+    }
+
+    /// Read an item inside `depth` lists
+    fn read(reader: &mut Reader<'a>, depth: usize) -> Result<Self, DecodeError>{
+        let prefix = *reader.bytes.first().ok_or(DecodeError::Truncated)?;
+        if prefix < 0x80 {
+            return Ok(Rlp::Bytes(reader.take(1)?));
+        }
+
+        reader.byte()?;
+        match prefix {
+            0x80..=0xbf => {
+                let length = Self::length(reader, prefix - 0x80)?;
+                let bytes = reader.take(length)?;
+                if length == 1 && bytes[0] < 0x80 {
+                    return Err(DecodeError::InvalidRlp);
+                }
+                Ok(Rlp::Bytes(bytes))
+            },
+            _ => {
+                if depth == MAX_DEPTH {
+                    return Err(DecodeError::TooDeep);
+                }
+                let length = Self::length(reader, prefix - 0xc0)?;
+                let mut payload = Reader { bytes: reader.take(length)? };
+                let mut items = Vec::new();
+                while !payload.bytes.is_empty() {
+                    items.push(Self::read(&mut payload, depth + 1)?);
+                }
+                Ok(Rlp::List(items))
+            },
+        }
+    }
+
+    /// Length of the payload, given what the prefix byte adds to its base
+    fn length(reader: &mut Reader, short: u8) -> Result<usize, DecodeError>{
+        if short < 56 {
+            return Ok(short as usize);
+        }
+
+        let size = (short - 55) as usize;
+        let bytes = reader.take(size)?;
+        if size > 8 || bytes[0] == 0 {
+            return Err(DecodeError::InvalidRlp);
+        }
+        let length = bytes.iter().fold(0u64, |length, byte| (length << 8) | *byte as u64) as usize;
+        if length < 56 {
+            return Err(DecodeError::InvalidRlp);
+        }
+
+        Ok(length)
+    }
+
+    pub fn as_bytes(&self) -> Option<&'a [u8]>{
+        match self {
+            Rlp::Bytes(bytes) => Some(bytes),
+            Rlp::List(_) => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Rlp<'a>]>{
+        match self {
+            Rlp::Bytes(_) => None,
+            Rlp::List(items) => Some(items),
+        }
+    }
+}
+
+/// Encoding of a byte string
+pub fn encode_bytes(bytes: &[u8]) -> Vec<u8>{
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec();
+    }
+
+    let mut encoded = prefix(bytes.len(), 0x80);
+    encoded.extend_from_slice(bytes);
+    encoded
+}
+
+/// Encoding of a list whose items are already encoded
+pub fn encode_list(items: &[Vec<u8>]) -> Vec<u8>{
+    let length = items.iter().map(Vec::len).sum();
+    let mut encoded = prefix(length, 0xc0);
+    for item in items {
+        encoded.extend_from_slice(item);
+    }
+    encoded
+}
+
+/// Big endian bytes of `value` without leading zeros, how RLP writes integers
+pub fn encode_uint(value: u128) -> Vec<u8>{
+    let bytes = value.to_be_bytes();
+    let first = bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len());
+    bytes[first..].to_vec()
+}
+
+/// Inverse of `encode_uint`, `None` if it has leading zeros or does not fit
+pub fn decode_uint(bytes: &[u8]) -> Option<u128>{
+    if bytes.len() > 16 || bytes.first() == Some(&0) {
+        return None;
+    }
+
+    Some(bytes.iter().fold(0u128, |value, byte| (value << 8) | *byte as u128))
+}
+
+fn prefix(length: usize, base: u8) -> Vec<u8>{
+    if length < 56 {
+        return vec![base + length as u8];
+    }
+
+    let size = encode_uint(length as u128);
+    let mut prefix = vec![base + 55 + size.len() as u8];
+    prefix.extend(size);
+    prefix
+}
+
+#[cfg(test)]
+mod test{
+    use super::*;
+
+    #[test]
+    fn round_trips(){
+        let long = [0xaau8; 60];
+        let cases: [(Vec<u8>, Rlp); 5] = [
+            (vec![0x80], Rlp::Bytes(&[])),
+            (vec![0x7f], Rlp::Bytes(&[0x7f])),
+            (vec![0x82, 0x04, 0x00], Rlp::Bytes(&[0x04, 0x00])),
+            ([&[0xb8, 60][..], &long].concat(), Rlp::Bytes(&long)),
+            (vec![0xc4, 0x83, b'd', b'o', b'g'], Rlp::List(vec![Rlp::Bytes(b"dog")])),
+        ];
+
+        for (encoded, item) in cases.iter() {
+            assert_eq!(&Rlp::decode(encoded).unwrap(), item);
+        }
+        assert_eq!(encode_bytes(&long), cases[3].0);
+        assert_eq!(encode_list(&[encode_bytes(b"dog")]), cases[4].0);
+        assert_eq!(encode_list(&[]), vec![0xc0]);
+        assert_eq!(decode_uint(&encode_uint(1024)), Some(1024));
+        assert_eq!(encode_uint(0), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn rejects_non_canonical(){
+        assert_eq!(Rlp::decode(&[0x81, 0x05]), Err(DecodeError::InvalidRlp));
+        assert_eq!(Rlp::decode(&[0xb8, 0x02, 0x01, 0x02]), Err(DecodeError::InvalidRlp));
+        assert_eq!(Rlp::decode(&[0x83, b'd', b'o']), Err(DecodeError::Truncated));
+        assert_eq!(Rlp::decode(&[0x80, 0x80]), Err(DecodeError::TrailingBytes));
+        assert_eq!(decode_uint(&[0x00, 0x01]), None);
+    }
+
+    #[test]
+    fn limits_nesting(){
+        // Every list holds the next one, down to an empty one
+        let nested = |depth: usize| (0..depth).fold(encode_list(&[]), |inner, _| encode_list(&[inner]));
+        assert!(Rlp::decode(&nested(MAX_DEPTH - 1)).is_ok());
+        assert_eq!(Rlp::decode(&nested(MAX_DEPTH)), Err(DecodeError::TooDeep));
+        assert_eq!(Rlp::decode(&nested(1000)), Err(DecodeError::TooDeep));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::hashers::{CryptoHash, CryptoHasher};
+
+use super::{combiner::NodeCombiner, VerificationError};
+
+/// Start and level of the perfect subtrees, the peaks, a list of `size` leaves
+/// decomposes into, from the biggest one, which holds the first leaves
+pub(crate) fn decompose(size: usize) -> Vec<(usize, usize)>{
+    let mut peaks = Vec::new();
+    let mut start = 0;
+    for level in (0..usize::BITS as usize).rev() {
+        if (size >> level) & 1 == 1 {
+            peaks.push((start, level));
+            start += 1 << level;
+        }
+    }
+
+    peaks
+}
+
+/// Position among the peaks of `size` leaves of the one of `2^level` leaves, there are as many before it as bigger ones
+pub(crate) fn position(size: usize, level: usize) -> usize{
+    (size >> (level + 1)).count_ones() as usize
+}
+
+/// Single root for all the peaks, the smallest one is the innermost
+pub(crate) fn bag<H: CryptoHasher, C: NodeCombiner>(peaks: &[CryptoHash]) -> CryptoHash{
+    let mut peaks = peaks.iter().rev();
+    let mut current = peaks.next().unwrap().clone();
+    for bigger in peaks {
+        current = C::combine::<H>(&current, bigger);
+    }
+
+    current
+}
+
+/// Root of the leaves in [left, rigth) of a list that starts with `old_size` leaves whose peaks are `old_peaks`.
+///
+/// The subtrees with only newer leaves are taken from `hashes`, depth first, lower indices first.
+/// The right child holds [left, mid).
+pub(crate) fn rebuild<'a, H: CryptoHasher, C: NodeCombiner>(
+    old_size: usize,
+    old_peaks: &[CryptoHash],
+    (left, rigth): (usize, usize),
+    hashes: &mut impl Iterator<Item = &'a CryptoHash>,
+) -> Result<CryptoHash, VerificationError>{
+    if left >= old_size {
+        return hashes.next().cloned().ok_or(VerificationError::MalformedProof);
+    }
+    if rigth <= old_size {
+        // An aligned subtree of only old leaves is always one of the old peaks
+        let level = (rigth - left).trailing_zeros() as usize;
+        return Ok(old_peaks[position(old_size, level)].clone());
+    }
+
+    let mid = (left + rigth) / 2;
+    let right = rebuild::<H,C>(old_size, old_peaks, (left, mid), hashes)?;
+    let left = rebuild::<H,C>(old_size, old_peaks, (mid, rigth), hashes)?;
+    Ok(C::combine::<H>(&left, &right))
+}
+
+/// Append `leaf` to the first of `levels`, which hold the roots of the aligned perfect
+/// subtrees of `2^level` leaves, and add the parents it completes.
+///
+/// `parent(lower, higher)` hashes a parent from its children.
+pub(crate) fn push_aligned(levels: &mut Vec<Vec<CryptoHash>>, leaf: CryptoHash, parent: impl Fn(&CryptoHash, &CryptoHash) -> CryptoHash){
+    levels[0].push(leaf);
+
+    // Every time a level gets an even amount of nodes, the last two have a parent
+    let mut level = 0;
+    while levels[level].len().is_multiple_of(2) {
+        let nodes = &levels[level];
+        let hash = parent(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+        if level + 1 == levels.len() {
+            levels.push(Vec::new());
+        }
+        levels[level + 1].push(hash);
+        level += 1;
+    }
+}
@@ -0,0 +1,405 @@
+use std::{fs::File, io::{self, Write, BufWriter}, path::Path};
+
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable, digest::HashOutput};
+
+use super::{merkle_tree::MerkleTree, combiner::NodeCombiner, inclusion_proof::{shape_tag, shape_from_tag, Reader}, TreeShape, DecodeError, MerkleError};
+
+const MAGIC: &[u8; 4] = b"MRKL";
+const VERSION: u8 = 1;
+/// Magic, version, shape, hash length and amount of leaves
+const HEADER_LEN: usize = MAGIC.len() + 3 + 8;
+/// What the hasher and the combiner are identified by, the hashes they give for it
+const PROBE: &[u8] = b"merkle tree file";
+
+/// Why a tree could not be written to or read from a file or a `NodeStore`
+#[derive(Debug)]
+pub enum PersistError{
+    Io(io::Error),
+    /// The file is not a tree, or it is cut short
+    Decode(DecodeError),
+    /// The file does not start like the files trees are saved to
+    NotATree,
+    /// The tree was saved with another `CryptoHasher`
+    WrongHasher,
+    /// The tree was saved with another `NodeCombiner`
+    WrongCombiner,
+    /// Not every hash of the tree has the same length, so they can't be stored by position
+    UnevenHashes,
+    /// The tree being saved could not be read from the file it was opened from
+    Tree(MerkleError),
+    /// A hash is longer than the 255 bytes a `FileStore` has room for
+    HashTooLong(usize),
+}
+
+impl From<io::Error> for PersistError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<MerkleError> for PersistError {
+    fn from(error: MerkleError) -> Self {
+        Self::Tree(error)
+    }
+}
+
+impl From<DecodeError> for PersistError {
+    fn from(error: DecodeError) -> Self {
+        Self::Decode(error)
+    }
+}
+
+impl<T: Hashable, D: HashOutput> MerkleTree<T, D> {
+    /// Write the tree to `path`, replacing what was there.
+    ///
+    /// After a header with the amount of leaves, the shape and what identifies `H`
+    /// and `C`, every level is stored from the leaves up, one hash after the other.
+    /// `H` and `C` must be the same ones the tree was built with.
+    pub fn save<H: CryptoHasher, C: NodeCombiner>(&self, path: impl AsRef<Path>) -> Result<(), PersistError>{
+        let (hasher, combiner) = fingerprints::<H,C>();
+        let digest_len = self.root().as_ref().len();
+        if digest_len > u8::MAX as usize || hasher.data.len() != digest_len || combiner.data.len() != digest_len {
+            return Err(PersistError::UnevenHashes);
+        }
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&[VERSION, shape_tag(self.shape()), digest_len as u8])?;
+        file.write_all(&(self.len() as u64).to_be_bytes())?;
+        file.write_all(&hasher.data)?;
+        file.write_all(&combiner.data)?;
+        for level in 0..=self.depth() {
+            for position in 0..self.width(level) {
+                let hash = self.hash_at(level, position)?;
+                let bytes: &[u8] = (*hash).as_ref();
+                if bytes.len() != digest_len {
+                    return Err(PersistError::UnevenHashes);
+                }
+                file.write_all(bytes)?;
+            }
+        }
+
+        file.flush()?;
+        Ok(())
+    }
+}
+
+impl<T: Hashable> MerkleTree<T> {
+    /// Reopen a tree saved with `save`, keeping the file open instead of reading it.
+    ///
+    /// Hashes are read from the file at their offset as they are needed, so proofs
+    /// are served without loading or hashing the tree again, and without mapping
+    /// the file into memory. If the file can't be read anymore, like when it is
+    /// cut short while open, proofs fail with `MerkleError::Io`.
+    /// The tree can't be updated until it is `load`ed, the file is never written.
+    pub fn open<H: CryptoHasher, C: NodeCombiner>(path: impl AsRef<Path>) -> Result<Self, PersistError>{
+        Self::open_file::<H, C>(path.as_ref())
+    }
+
+    /// Same as `open`, for trees built with `from_data_digest`
+    pub fn open_digest<H: CryptoHasher, C: NodeCombiner>(path: impl AsRef<Path>) -> Result<MerkleTree<T, H::Output>, PersistError>{
+        MerkleTree::<T, H::Output>::open_file::<H, C>(path.as_ref())
+    }
+}
+
+impl<T: Hashable, D: HashOutput> MerkleTree<T, D> {
+    fn open_file<H: CryptoHasher, C: NodeCombiner>(path: &Path) -> Result<Self, PersistError>{
+        let file = TreeFile::open(path)?;
+
+        let header = file.read(0, HEADER_LEN)?;
+        let mut reader = Reader { bytes: &header };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(PersistError::NotATree);
+        }
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version).into());
+        }
+
+        let shape = shape_from_tag(reader.byte()?)?;
+        let digest_len = reader.byte()? as usize;
+        let stored_len = reader.u64()?;
+        let len = usize::try_from(stored_len).map_err(|_| DecodeError::TooManyLeaves(stored_len))?;
+
+        let fingerprinted = file.read(HEADER_LEN, digest_len * 2)?;
+        let mut reader = Reader { bytes: &fingerprinted };
+        let (hasher, combiner) = fingerprints::<H,C>();
+        if CryptoHash::from(reader.take(digest_len)?) != hasher {
+            return Err(PersistError::WrongHasher);
+        }
+        if CryptoHash::from(reader.take(digest_len)?) != combiner {
+            return Err(PersistError::WrongCombiner);
+        }
+        if len == 0 {
+            return Err(DecodeError::Truncated.into());
+        }
+
+        let levels = FileLevels::new(file, HEADER_LEN + digest_len * 2, digest_len, len, shape)?;
+        Ok(Self::from_file::<H>(levels, len, shape))
+    }
+}
+
+/// Hashes `H` and `C` give for `PROBE`, different hashers or combiners give different ones
+fn fingerprints<H: CryptoHasher, C: NodeCombiner>() -> (CryptoHash, CryptoHash){
+    let plain = H::hash(PROBE);
+    let hasher = H::hash_node(&[plain.data.as_slice(), &H::hash_leaf(PROBE).data].concat());
+    let combiner = C::combine::<H>(&hasher, &plain);
+    (hasher, combiner)
+}
+
+/// Levels of a tree as they are laid out in its file
+pub(crate) struct FileLevels<D: HashOutput>{
+    file: TreeFile,
+    /// Where every level starts in `file`
+    starts: Vec<usize>,
+    widths: Vec<usize>,
+    digest_len: usize,
+    root: D,
+}
+
+impl<D: HashOutput> FileLevels<D> {
+    fn new(file: TreeFile, start: usize, digest_len: usize, len: usize, shape: TreeShape) -> Result<Self, PersistError>{
+        let widths = stored_widths(len, shape)?;
+        let mut starts = Vec::with_capacity(widths.len());
+        let mut end = start;
+        for width in &widths {
+            starts.push(end);
+            end = width.checked_mul(digest_len)
+                .and_then(|size| end.checked_add(size))
+                .ok_or(PersistError::Decode(DecodeError::Truncated))?;
+        }
+
+        if file.len < end {
+            return Err(DecodeError::Truncated.into());
+        }
+        if file.len > end {
+            return Err(DecodeError::TrailingBytes.into());
+        }
+
+        let root = D::from_slice(&file.read(end - digest_len, digest_len)?).ok_or(PersistError::UnevenHashes)?;
+        Ok(Self { file, starts, widths, digest_len, root })
+    }
+
+    pub(crate) fn root(&self) -> &D{
+        &self.root
+    }
+
+    pub(crate) fn width(&self, level: usize) -> usize{
+        self.widths[level]
+    }
+
+    /// Hash at `position` of `level`, `None` past the ones stored
+    pub(crate) fn get(&self, level: usize, position: usize) -> io::Result<Option<D>>{
+        if position >= self.widths[level] {
+            return Ok(None);
+        }
+
+        let mut buffer = [0u8; u8::MAX as usize];
+        let bytes = &mut buffer[..self.digest_len];
+        self.file.read_exact_at(bytes, self.starts[level] + position * self.digest_len)?;
+        Ok(D::from_slice(bytes))
+    }
+
+    /// Every level read into memory
+    pub(crate) fn load(&self) -> io::Result<Vec<Vec<D>>>{
+        (0..self.widths.len()).map(|level| {
+            let mut bytes = vec![0; self.widths[level] * self.digest_len];
+            self.file.read_exact_at(&mut bytes, self.starts[level])?;
+            // The root has this length, so every hash fits in `D`
+            Ok(bytes.chunks(self.digest_len).filter_map(D::from_slice).collect())
+        }).collect()
+    }
+}
+
+/// Amount of hashes stored for every level, the partial shapes don't store their padding
+fn stored_widths(len: usize, shape: TreeShape) -> Result<Vec<usize>, DecodeError>{
+    let mut width = match shape {
+        TreeShape::FullCopyExtend | TreeShape::FullNullExtend => len.checked_next_power_of_two()
+            .ok_or(DecodeError::TooManyLeaves(len as u64))?,
+        TreeShape::PartialCopyExtend | TreeShape::PartialNullExtend => len,
+    };
+
+    let mut widths = vec![width];
+    while width > 1 {
+        width = width.div_ceil(2);
+        widths.push(width);
+    }
+
+    Ok(widths)
+}
+
+/// A saved tree, hashes are read from it by their offset
+struct TreeFile{
+    file: File,
+    len: usize,
+}
+
+impl TreeFile {
+    fn open(path: &Path) -> io::Result<Self>{
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        Ok(Self { file, len })
+    }
+
+    fn read_exact_at(&self, buffer: &mut [u8], offset: usize) -> io::Result<()>{
+        read_exact_at(&self.file, buffer, offset as u64)
+    }
+
+    /// Up to `amount` bytes from `offset`, fewer if the file ends first
+    fn read(&self, offset: usize, amount: usize) -> io::Result<Vec<u8>>{
+        let end = offset.saturating_add(amount).min(self.len);
+        let mut bytes = vec![0; end.saturating_sub(offset)];
+        self.read_exact_at(&mut bytes, offset)?;
+        Ok(bytes)
+    }
+}
+
+/// Fill `buffer` from `offset` without moving the cursor of `file`, so it can be read from several threads
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()>{
+    use std::os::unix::fs::FileExt;
+
+    file.read_exact_at(buffer, offset)
+}
+
+#[cfg(windows)]
+pub(crate) fn read_exact_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> io::Result<()>{
+    use std::os::windows::fs::FileExt;
+
+    while !buffer.is_empty() {
+        match file.seek_read(buffer, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            read => {
+                buffer = &mut buffer[read..];
+                offset += read as u64;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Where there are no positioned reads the cursor is moved, reads from several threads have to be serialized
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn read_exact_at(mut file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()>{
+    use std::io::{Read, Seek, SeekFrom};
+
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buffer)
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::{sha256::SHA256, keccak256::Keccak256}, encoding::hex::Hex, merkle::combiner::RawBytes};
+
+    use crate::merkle::testing::{data, shapes, path};
+    use super::*;
+
+    #[test]
+    fn reopened_trees_serve_the_same_proofs(){
+        let data = data(11);
+        for shape in shapes() {
+            let path = path(&format!("{:?}.tree", shape));
+            let tree = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+            tree.save::<SHA256, Hex>(&path).unwrap();
+
+            let mut reopened = MerkleTree::<String>::open::<SHA256, Hex>(&path).unwrap();
+            assert_eq!(reopened.root().data, tree.root().data);
+            assert_eq!((reopened.len(), reopened.shape()), (tree.len(), tree.shape()));
+            for (i, leaf) in data.iter().enumerate() {
+                let trace = reopened.generate_trace(i).unwrap();
+                assert_eq!(trace.verify::<SHA256, Hex, _>(leaf, i, tree.root()), Ok(()), "{} in {:?}", i, shape);
+            }
+            let multi = reopened.generate_multi_trace(&[0, 4, 10]).unwrap();
+            assert_eq!(multi.verify::<SHA256, Hex, _>(&[(0, &data[0]), (4, &data[4]), (10, &data[10])], tree.root()), Ok(()));
+            assert_eq!(reopened.diff(&tree).unwrap(), vec![]);
+
+            // Updating needs it loaded, the file stays as it was
+            let mut updated = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+            let changed = "changed".to_string();
+            assert_eq!(reopened.update::<SHA256, Hex>(3, &changed), Err(MerkleError::FileBacked));
+            reopened.load().unwrap();
+            reopened.update::<SHA256, Hex>(3, &changed).unwrap();
+            updated.update::<SHA256, Hex>(3, &changed).unwrap();
+            assert_eq!(reopened.root().data, updated.root().data);
+            assert_eq!(MerkleTree::<String>::open::<SHA256, Hex>(&path).unwrap().root().data, tree.root().data);
+
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn unreadable_files_fail_proofs(){
+        let (path, copy) = (path("unreadable.tree"), path("copy.tree"));
+        let tree = MerkleTree::from_data::<SHA256, Hex>(&data(8), TreeShape::FullNullExtend);
+        tree.save::<SHA256, Hex>(&path).unwrap();
+
+        let mut reopened = MerkleTree::<String>::open::<SHA256, Hex>(&path).unwrap();
+        // Cut the leaves short while it is open
+        File::options().write(true).open(&path).unwrap().set_len(HEADER_LEN as u64 + 32 * 5).unwrap();
+        assert_eq!(reopened.generate_trace(7).map(|_| ()), Err(MerkleError::Io(io::ErrorKind::UnexpectedEof)));
+        assert_eq!(reopened.load(), Err(MerkleError::Io(io::ErrorKind::UnexpectedEof)));
+        assert!(matches!(reopened.save::<SHA256, Hex>(&copy), Err(PersistError::Tree(MerkleError::Io(_)))));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&copy).unwrap();
+    }
+
+    #[test]
+    fn digest_trees_reopen(){
+        let path = path("digest.tree");
+        let data = data(9);
+        let tree = MerkleTree::from_data_digest::<Keccak256, RawBytes>(&data, TreeShape::PartialCopyExtend);
+        tree.save::<Keccak256, RawBytes>(&path).unwrap();
+
+        let reopened = MerkleTree::<String>::open_digest::<Keccak256, RawBytes>(&path).unwrap();
+        assert_eq!(reopened.root(), tree.root());
+        // The same file, whichever way the hashes are kept
+        let plain = MerkleTree::<String>::open::<Keccak256, RawBytes>(&path).unwrap();
+        assert_eq!(plain.root().data, tree.root().as_ref());
+        assert_eq!(reopened.generate_trace(8).unwrap().verify::<Keccak256, RawBytes, _>(&data[8], 8, &plain.root().clone()), Ok(()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn only_opens_with_the_same_hasher_and_combiner(){
+        let path = path("fingerprints.tree");
+        let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data(5), TreeShape::PartialNullExtend);
+        tree.save::<SHA256, RawBytes>(&path).unwrap();
+
+        assert!(MerkleTree::<String>::open::<SHA256, RawBytes>(&path).is_ok());
+        assert!(matches!(MerkleTree::<String>::open::<Keccak256, RawBytes>(&path), Err(PersistError::WrongHasher)));
+        assert!(matches!(MerkleTree::<String>::open::<SHA256, Hex>(&path), Err(PersistError::WrongCombiner)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_damaged_files(){
+        let path = path("damaged.tree");
+        let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data(6), TreeShape::FullNullExtend);
+        tree.save::<SHA256, RawBytes>(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        // Header, both fingerprints and 8 + 4 + 2 + 1 hashes
+        assert_eq!(bytes.len(), 15 + 32 * 2 + 32 * 15);
+
+        let reopen = |bytes: &[u8]| {
+            std::fs::write(&path, bytes).unwrap();
+            MerkleTree::<String>::open::<SHA256, RawBytes>(&path).err()
+        };
+        assert!(matches!(reopen(&bytes[..bytes.len() - 1]), Some(PersistError::Decode(DecodeError::Truncated))));
+        assert!(matches!(reopen(&[&bytes[..], &[0]].concat()), Some(PersistError::Decode(DecodeError::TrailingBytes))));
+        assert!(matches!(reopen(b"MRK"), Some(PersistError::Decode(DecodeError::Truncated))));
+        assert!(matches!(reopen(b"not a tree file"), Some(PersistError::NotATree)));
+
+        let mut versioned = bytes.clone();
+        versioned[4] = 9;
+        assert!(matches!(reopen(&versioned), Some(PersistError::Decode(DecodeError::UnsupportedVersion(9)))));
+        let mut shaped = bytes.clone();
+        shaped[5] = 7;
+        assert!(matches!(reopen(&shaped), Some(PersistError::Decode(DecodeError::UnknownShape(7)))));
+        // No power of two fits that many leaves
+        let mut huge = bytes;
+        huge[7..15].copy_from_slice(&(usize::MAX as u64).to_be_bytes());
+        assert!(matches!(reopen(&huge), Some(PersistError::Decode(DecodeError::TooManyLeaves(_)))));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
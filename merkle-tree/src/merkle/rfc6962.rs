@@ -0,0 +1,391 @@
+use std::marker::PhantomData;
+
+use crate::{hashers::{CryptoHash, CryptoHasher, Hashable, separated::{Separated, ByteTags}}, encoding::Digestable};
+
+use super::{peaks::push_aligned, MerkleError, VerificationError};
+
+/// Merkle Tree Hash of RFC 6962, the one used by Certificate Transparency logs.
+///
+/// It does not fit any `TreeShape`: trees are split at the largest power of two
+/// smaller than their size instead of being padded, leaves and nodes are hashed
+/// with different prefixes, and children are hashed as raw bytes.
+/// Use `SHA256` as the hasher to get the same hashes as the logs.
+pub struct Rfc6962Tree<T: Hashable>{
+    /// Roots of the aligned perfect subtrees of `2^level` leaves, by level
+    levels: Vec<Vec<CryptoHash>>,
+    src: PhantomData<T>
+}
+
+impl<T: Hashable> Rfc6962Tree<T> {
+    pub fn new() -> Self{
+        Self { levels: vec![Vec::new()], src: PhantomData }
+    }
+
+    pub fn from_data<H: CryptoHasher>(data: &[T]) -> Self{
+        let mut tree = Self::new();
+        for datoid in data {
+            tree.push::<H>(datoid);
+        }
+
+        tree
+    }
+
+    pub fn len(&self) -> usize{
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.levels[0].is_empty()
+    }
+
+    pub fn push<H: CryptoHasher>(&mut self, leaf: &T){
+        push_aligned(&mut self.levels, leaf_hash::<H, _>(leaf), node_hash::<H>);
+    }
+
+    /// Root of the whole tree
+    pub fn root<H: CryptoHasher>(&self) -> CryptoHash{
+        self.subtree::<H>(0, self.len())
+    }
+
+    /// Root the tree had when it had only its first `size` leaves
+    pub fn root_at<H: CryptoHasher>(&self, size: usize) -> Result<CryptoHash, MerkleError>{
+        if size > self.len() {
+            return Err(MerkleError::SizeOutOfBounds { size, len: self.len() });
+        }
+
+        Ok(self.subtree::<H>(0, size))
+    }
+
+    /// Audit path of the leaf at `index` in the tree of the first `size` leaves, from the leaf up
+    pub fn audit_path<H: CryptoHasher>(&self, index: usize, size: usize) -> Result<Vec<CryptoHash>, MerkleError>{
+        if size > self.len() {
+            return Err(MerkleError::SizeOutOfBounds { size, len: self.len() });
+        }
+        if index >= size {
+            return Err(MerkleError::IndexOutOfBounds { index, len: size });
+        }
+
+        let mut path = Vec::new();
+        self.path::<H>(index, 0, size, &mut path);
+        Ok(path)
+    }
+
+    /// Proof that the tree of the first `old_size` leaves is a prefix of the one of `new_size`
+    pub fn consistency_proof<H: CryptoHasher>(&self, old_size: usize, new_size: usize) -> Result<Vec<CryptoHash>, MerkleError>{
+        if new_size > self.len() {
+            return Err(MerkleError::SizeOutOfBounds { size: new_size, len: self.len() });
+        }
+        if old_size > new_size {
+            return Err(MerkleError::SizeOutOfBounds { size: old_size, len: new_size });
+        }
+
+        let mut proof = Vec::new();
+        if old_size > 0 {
+            self.subproof::<H>(old_size, 0, new_size, true, &mut proof);
+        }
+        Ok(proof)
+    }
+
+    /// MTH(D[start:end])
+    fn subtree<H: CryptoHasher>(&self, start: usize, end: usize) -> CryptoHash{
+        let size = end - start;
+        if size == 0 {
+            return H::hash(&[]);
+        }
+        if size.is_power_of_two() {
+            let level = size.trailing_zeros() as usize;
+            return self.levels[level][start >> level].clone();
+        }
+
+        let k = split(size);
+        node_hash::<H>(&self.subtree::<H>(start, start + k), &self.subtree::<H>(start + k, end))
+    }
+
+    /// PATH(m, D[start:end])
+    fn path<H: CryptoHasher>(&self, index: usize, start: usize, end: usize, path: &mut Vec<CryptoHash>){
+        if end - start <= 1 {
+            return;
+        }
+
+        let k = split(end - start);
+        if index < k {
+            self.path::<H>(index, start, start + k, path);
+            path.push(self.subtree::<H>(start + k, end));
+        }else{
+            self.path::<H>(index - k, start + k, end, path);
+            path.push(self.subtree::<H>(start, start + k));
+        }
+    }
+
+    /// SUBPROOF(m, D[start:end], b)
+    fn subproof<H: CryptoHasher>(&self, m: usize, start: usize, end: usize, complete: bool, proof: &mut Vec<CryptoHash>){
+        if m == end - start {
+            if !complete {
+                proof.push(self.subtree::<H>(start, end));
+            }
+            return;
+        }
+
+        let k = split(end - start);
+        if m <= k {
+            self.subproof::<H>(m, start, start + k, complete, proof);
+            proof.push(self.subtree::<H>(start + k, end));
+        }else{
+            self.subproof::<H>(m - k, start + k, end, false, proof);
+            proof.push(self.subtree::<H>(start, start + k));
+        }
+    }
+}
+
+impl<T: Hashable> Default for Rfc6962Tree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Check an audit path, as described in section 2.1.3.2 of RFC 9162
+pub fn verify_audit_path<H: CryptoHasher, V: Hashable + ?Sized>(leaf: &V, index: usize, size: usize, path: &[CryptoHash], root: &CryptoHash) -> Result<(), VerificationError>{
+    if index >= size {
+        return Err(VerificationError::WrongIndex);
+    }
+
+    let mut f_n = index;
+    let mut s_n = size - 1;
+    let mut current = leaf_hash::<H, _>(leaf);
+    for sibling in path {
+        if s_n == 0 {
+            return Err(VerificationError::MalformedProof);
+        }
+
+        if f_n & 1 == 1 || f_n == s_n {
+            current = node_hash::<H>(sibling, &current);
+            while f_n & 1 == 0 && f_n != 0 {
+                f_n >>= 1;
+                s_n >>= 1;
+            }
+        }else{
+            current = node_hash::<H>(&current, sibling);
+        }
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+
+    if s_n != 0 {
+        return Err(VerificationError::MalformedProof);
+    }
+    if current != *root {
+        return Err(VerificationError::WrongRoot);
+    }
+
+    Ok(())
+}
+
+/// Check a consistency proof, as described in section 2.1.4.2 of RFC 9162
+pub fn verify_consistency<H: CryptoHasher>(old_size: usize, new_size: usize, old_root: &CryptoHash, new_root: &CryptoHash, proof: &[CryptoHash]) -> Result<(), VerificationError>{
+    if old_size > new_size {
+        return Err(VerificationError::MalformedProof);
+    }
+    if old_size == 0 || old_size == new_size {
+        if !proof.is_empty() {
+            return Err(VerificationError::MalformedProof);
+        }
+        if old_size == new_size && old_root != new_root {
+            return Err(VerificationError::WrongRoot);
+        }
+        return Ok(());
+    }
+
+    // The old root is only left out of the proof when it is a whole subtree of the new tree
+    let mut path: Vec<&CryptoHash> = Vec::with_capacity(proof.len() + 1);
+    if old_size.is_power_of_two() {
+        path.push(old_root);
+    }
+    path.extend(proof);
+    if path.is_empty() {
+        return Err(VerificationError::MalformedProof);
+    }
+
+    let mut f_n = old_size - 1;
+    let mut s_n = new_size - 1;
+    while f_n & 1 == 1 {
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+
+    let mut old = path[0].clone();
+    let mut new = path[0].clone();
+    for sibling in &path[1..] {
+        if s_n == 0 {
+            return Err(VerificationError::MalformedProof);
+        }
+
+        if f_n & 1 == 1 || f_n == s_n {
+            old = node_hash::<H>(sibling, &old);
+            new = node_hash::<H>(sibling, &new);
+            while f_n & 1 == 0 && f_n != 0 {
+                f_n >>= 1;
+                s_n >>= 1;
+            }
+        }else{
+            new = node_hash::<H>(&new, sibling);
+        }
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+
+    if s_n != 0 {
+        return Err(VerificationError::MalformedProof);
+    }
+    if old != *old_root {
+        return Err(VerificationError::WrongOldRoot);
+    }
+    if new != *new_root {
+        return Err(VerificationError::WrongRoot);
+    }
+
+    Ok(())
+}
+
+/// Largest power of two smaller than `size`, which must be at least 2
+fn split(size: usize) -> usize{
+    1 << (usize::BITS - 1 - (size - 1).leading_zeros())
+}
+
+/// HASH(0x00 || d)
+pub fn leaf_hash<H: CryptoHasher, V: Hashable + ?Sized>(leaf: &V) -> CryptoHash{
+    Separated::<H, ByteTags>::hash_leaf(leaf.to_bits())
+}
+
+/// HASH(0x01 || left || right), the left child holds the lower indices
+pub fn node_hash<H: CryptoHasher>(left: &CryptoHash, right: &CryptoHash) -> CryptoHash{
+    let mut children = Vec::with_capacity(left.bits().len() + right.bits().len());
+    children.extend_from_slice(left.bits());
+    children.extend_from_slice(right.bits());
+    Separated::<H, ByteTags>::hash_node(&children)
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, encoding::{Digester, hex::Hex}};
+
+    use super::*;
+
+    /// Test vectors used by the Certificate Transparency implementations
+    const LEAVES: [&str; 8] = ["", "00", "10", "2021", "3031", "40414243", "5051525354555657", "606162636465666768696a6b6c6d6e6f"];
+    const ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    fn leaves() -> Vec<Vec<u8>>{
+        LEAVES.iter().map(|leaf| Hex::undigest(leaf).unwrap()).collect()
+    }
+
+    fn hashes(hex: &[&str]) -> Vec<CryptoHash>{
+        hex.iter().map(|h| CryptoHash { data: Hex::undigest(h).unwrap() }).collect()
+    }
+
+    fn digests(hashes: &[CryptoHash]) -> Vec<String>{
+        hashes.iter().map(|h| h.digest::<Hex>().to_lowercase()).collect()
+    }
+
+    #[test]
+    fn roots(){
+        let tree = Rfc6962Tree::from_data::<SHA256>(&leaves());
+        assert_eq!(tree.root_at::<SHA256>(0).unwrap().digest::<Hex>().to_lowercase(), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        for (size, root) in ROOTS.iter().enumerate() {
+            assert_eq!(tree.root_at::<SHA256>(size + 1).unwrap().digest::<Hex>().to_lowercase(), *root);
+            let prefix = Rfc6962Tree::from_data::<SHA256>(&leaves()[..=size]);
+            assert_eq!(prefix.root::<SHA256>().digest::<Hex>().to_lowercase(), *root);
+        }
+    }
+
+    #[test]
+    fn audit_paths(){
+        let tree = Rfc6962Tree::from_data::<SHA256>(&leaves());
+        let vectors: [(usize, usize, Vec<&str>); 5] = [
+            (0, 1, vec![]),
+            (0, 8, vec![
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ]),
+            (5, 8, vec![
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ]),
+            (2, 3, vec!["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"]),
+            (1, 5, vec![
+                "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ]),
+        ];
+
+        for (index, size, expected) in vectors {
+            let path = tree.audit_path::<SHA256>(index, size).unwrap();
+            assert_eq!(digests(&path), expected);
+            let root = tree.root_at::<SHA256>(size).unwrap();
+            assert_eq!(verify_audit_path::<SHA256, _>(&leaves()[index], index, size, &path, &root), Ok(()));
+        }
+    }
+
+    #[test]
+    fn every_audit_path_verifies(){
+        let leaves = leaves();
+        let tree = Rfc6962Tree::from_data::<SHA256>(&leaves);
+        for size in 1..=8 {
+            let root = tree.root_at::<SHA256>(size).unwrap();
+            for index in 0..size {
+                let path = tree.audit_path::<SHA256>(index, size).unwrap();
+                assert_eq!(verify_audit_path::<SHA256, _>(&leaves[index], index, size, &path, &root), Ok(()));
+                assert_eq!(verify_audit_path::<SHA256, _>(&leaves[(index + 1) % 8], index, size, &path, &root), Err(VerificationError::WrongRoot));
+            }
+        }
+        assert!(tree.audit_path::<SHA256>(3, 3).is_err());
+    }
+
+    #[test]
+    fn consistency_proofs(){
+        let tree = Rfc6962Tree::from_data::<SHA256>(&leaves());
+        let vectors: [(usize, usize, Vec<&str>); 4] = [
+            (1, 1, vec![]),
+            (1, 8, vec![
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ]),
+            (6, 8, vec![
+                "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ]),
+            (2, 5, vec![
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ]),
+        ];
+
+        for (old, new, expected) in vectors {
+            assert_eq!(digests(&tree.consistency_proof::<SHA256>(old, new).unwrap()), expected);
+        }
+
+        let roots = hashes(&ROOTS);
+        for old in 1..=8 {
+            for new in old..=8 {
+                let proof = tree.consistency_proof::<SHA256>(old, new).unwrap();
+                assert_eq!(verify_consistency::<SHA256>(old, new, &roots[old - 1], &roots[new - 1], &proof), Ok(()));
+                if old != new {
+                    assert!(verify_consistency::<SHA256>(old, new, &roots[new - 1], &roots[new - 1], &proof).is_err());
+                }
+            }
+        }
+    }
+}
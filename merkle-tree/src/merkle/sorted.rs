@@ -0,0 +1,241 @@
+use std::marker::PhantomData;
+
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable};
+
+use super::{node::Node, combiner::NodeCombiner, merkle_tree::MerkleTree, merkle_trace::MerkleTrace, TreeShape, MerkleError, VerificationError};
+
+/// What the leaves of a `SortedMerkleTree` are sorted by
+pub trait LeafOrder{
+    /// Sort key of a leaf, keys are compared as bytes
+    fn key<H: CryptoHasher>(bits: &[u8]) -> Vec<u8>;
+}
+
+/// Sorts the leaves by their bytes
+pub struct ByValue{
+    non_instance: PhantomData<bool>,
+}
+
+impl LeafOrder for ByValue{
+    fn key<H: CryptoHasher>(bits: &[u8]) -> Vec<u8> {
+        bits.to_vec()
+    }
+}
+
+/// Sorts the leaves by their hash
+pub struct ByHash{
+    non_instance: PhantomData<bool>,
+}
+
+impl LeafOrder for ByHash{
+    fn key<H: CryptoHasher>(bits: &[u8]) -> Vec<u8> {
+        Node::hash_leaf::<H, _>(bits).data
+    }
+}
+
+/// `MerkleTree` whose leaves are sorted by the key `O` gives them, so a value
+/// can be shown to be absent by proving the two leaves that would surround it.
+///
+/// Values with the same key are kept once.
+pub struct SortedMerkleTree<T: Hashable, O: LeafOrder>{
+    tree: MerkleTree<T>,
+    data: Vec<T>,
+    keys: Vec<Vec<u8>>,
+    order: PhantomData<O>,
+}
+
+impl<T: Hashable, O: LeafOrder> SortedMerkleTree<T, O> {
+    pub fn from_data<H: CryptoHasher, C: NodeCombiner>(data: Vec<T>, tree_shape: TreeShape) -> Self{
+        let mut keyed: Vec<(Vec<u8>, T)> = data.into_iter()
+            .map(|datoid| (O::key::<H>(datoid.to_bits()), datoid))
+            .collect();
+        keyed.sort_by(|a, b| a.0.cmp(&b.0));
+        keyed.dedup_by(|later, earlier| later.0 == earlier.0);
+
+        let (keys, data): (Vec<_>, Vec<_>) = keyed.into_iter().unzip();
+        let tree = MerkleTree::from_data::<H,C>(&data, tree_shape);
+        Self { tree, data, keys, order: PhantomData }
+    }
+
+    /// The tree over the sorted leaves
+    pub fn tree(&self) -> &MerkleTree<T>{
+        &self.tree
+    }
+
+    pub fn root(&self) -> &CryptoHash{
+        self.tree.root()
+    }
+
+    pub fn len(&self) -> usize{
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.data.is_empty()
+    }
+
+    /// The leaves, sorted
+    pub fn data(&self) -> &[T]{
+        &self.data
+    }
+
+    /// Index of `value` if it is in the tree, or else the index it would be inserted at
+    pub fn position<H: CryptoHasher, V: Hashable + ?Sized>(&self, value: &V) -> Result<usize, usize>{
+        let key = O::key::<H>(value.to_bits());
+        self.keys.binary_search(&key)
+    }
+
+    /// Proof that `value` is not in the tree.
+    ///
+    /// `H` must be the same one the tree was built with.
+    pub fn prove_absence<H: CryptoHasher, V: Hashable + ?Sized>(&self, value: &V) -> Result<ExclusionProof, MerkleError>{
+        let index = match self.position::<H, _>(value) {
+            Ok(index) => return Err(MerkleError::Present { index }),
+            Err(index) => index,
+        };
+
+        let neighbour = |index: usize| -> Result<Neighbour, MerkleError> {
+            Ok(Neighbour { value: self.data[index].to_bits().to_vec(), trace: self.tree.generate_trace(index)? })
+        };
+
+        let lower = if index > 0 { Some(neighbour(index - 1)?) } else { None };
+        let higher = if index < self.len() { Some(neighbour(index)?) } else { None };
+        Ok(ExclusionProof { lower, higher })
+    }
+}
+
+/// A leaf next to where an absent value would be, and the proof that it is in the tree
+pub struct Neighbour{
+    value: Vec<u8>,
+    trace: MerkleTrace,
+}
+
+impl Neighbour {
+    /// Bytes of the leaf
+    pub fn value(&self) -> &[u8]{
+        &self.value
+    }
+
+    pub fn trace(&self) -> &MerkleTrace{
+        &self.trace
+    }
+}
+
+/// Proof that a value is not in a `SortedMerkleTree`.
+///
+/// It holds the leaves right below and right above where the value would be,
+/// only one of them if it would be the first or the last one.
+pub struct ExclusionProof{
+    lower: Option<Neighbour>,
+    higher: Option<Neighbour>,
+}
+
+impl ExclusionProof {
+    pub fn lower(&self) -> Option<&Neighbour>{
+        self.lower.as_ref()
+    }
+
+    pub fn higher(&self) -> Option<&Neighbour>{
+        self.higher.as_ref()
+    }
+
+    /// Check that `value` is not in the tree of `len` leaves whose root is `root`.
+    ///
+    /// Both neighbours have to be in the tree, one right after the other, and
+    /// sorted around the value. The length has to be trusted like the root, a
+    /// proof for a longer tree could pass padding off as its last leaf.
+    /// `H`, `C` and `O` must be the same ones the tree was built with.
+    pub fn verify<H: CryptoHasher, C: NodeCombiner, O: LeafOrder, V: Hashable + ?Sized>(&self, value: &V, len: usize, root: &CryptoHash) -> Result<(), VerificationError>{
+        let key = O::key::<H>(value.to_bits());
+
+        for neighbour in self.lower.iter().chain(self.higher.iter()) {
+            if neighbour.trace.len() != len {
+                return Err(VerificationError::MalformedProof);
+            }
+            neighbour.trace.verify::<H, C, _>(neighbour.value.as_slice(), neighbour.trace.index(), root)?;
+        }
+
+        match (&self.lower, &self.higher) {
+            (None, None) => return Err(VerificationError::MalformedProof),
+            (Some(lower), None) if lower.trace.index() + 1 != len => return Err(VerificationError::WrongIndex),
+            (None, Some(higher)) if higher.trace.index() != 0 => return Err(VerificationError::WrongIndex),
+            (Some(lower), Some(higher)) if lower.trace.index() + 1 != higher.trace.index() => return Err(VerificationError::WrongIndex),
+            _ => {},
+        }
+
+        if let Some(lower) = &self.lower {
+            if O::key::<H>(&lower.value) >= key {
+                return Err(VerificationError::WrongOrder);
+            }
+        }
+        if let Some(higher) = &self.higher {
+            if O::key::<H>(&higher.value) <= key {
+                return Err(VerificationError::WrongOrder);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::{sha256::SHA256, separated::{Separated, ByteTags}}, merkle::combiner::RawBytes};
+
+    use crate::merkle::testing::shapes;
+    use super::*;
+
+    type Tagged = Separated<SHA256, ByteTags>;
+
+    fn serials() -> Vec<String>{
+        [17, 3, 42, 8, 23, 3, 15].iter().map(|serial| format!("serial {:03}", serial)).collect()
+    }
+
+    #[test]
+    fn sorts_and_dedups(){
+        let tree = SortedMerkleTree::<_, ByValue>::from_data::<Tagged, RawBytes>(serials(), TreeShape::PartialNullExtend);
+        assert_eq!(tree.data(), &["serial 003", "serial 008", "serial 015", "serial 017", "serial 023", "serial 042"]);
+        assert_eq!(tree.position::<Tagged, _>("serial 015"), Ok(2));
+        assert_eq!(tree.position::<Tagged, _>("serial 016"), Err(3));
+
+        let plain = MerkleTree::from_data::<Tagged, RawBytes>(tree.data(), TreeShape::PartialNullExtend);
+        assert_eq!(tree.root().data, plain.root().data);
+    }
+
+    #[test]
+    fn proves_absence(){
+        for shape in shapes() {
+            let tree = SortedMerkleTree::<_, ByValue>::from_data::<Tagged, RawBytes>(serials(), shape);
+            for absent in ["serial 000", "serial 010", "serial 016", "serial 030", "serial 099"] {
+                let proof = tree.prove_absence::<Tagged, _>(absent).unwrap();
+                assert_eq!(proof.verify::<Tagged, RawBytes, ByValue, _>(absent, tree.len(), tree.root()), Ok(()), "{} in {:?}", absent, shape);
+            }
+
+            assert_eq!(tree.prove_absence::<Tagged, _>("serial 023").err(), Some(MerkleError::Present { index: 4 }));
+        }
+
+        let tree = SortedMerkleTree::<_, ByHash>::from_data::<Tagged, RawBytes>(serials(), TreeShape::PartialCopyExtend);
+        let proof = tree.prove_absence::<Tagged, _>("serial 016").unwrap();
+        assert_eq!(proof.verify::<Tagged, RawBytes, ByHash, _>("serial 016", tree.len(), tree.root()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_present_values(){
+        let tree = SortedMerkleTree::<_, ByValue>::from_data::<Tagged, RawBytes>(serials(), TreeShape::PartialNullExtend);
+        let proof = tree.prove_absence::<Tagged, _>("serial 016").unwrap();
+
+        // The neighbours are right, but they don't surround these values
+        assert_eq!(proof.verify::<Tagged, RawBytes, ByValue, _>("serial 015", tree.len(), tree.root()), Err(VerificationError::WrongOrder));
+        assert_eq!(proof.verify::<Tagged, RawBytes, ByValue, _>("serial 017", tree.len(), tree.root()), Err(VerificationError::WrongOrder));
+        assert_eq!(proof.verify::<Tagged, RawBytes, ByValue, _>("serial 016", tree.len() + 1, tree.root()), Err(VerificationError::MalformedProof));
+
+        // Leaves that are in the tree but not next to each other
+        let gap = ExclusionProof {
+            lower: Some(Neighbour { value: b"serial 008".to_vec(), trace: tree.tree().generate_trace(1).unwrap() }),
+            higher: Some(Neighbour { value: b"serial 017".to_vec(), trace: tree.tree().generate_trace(3).unwrap() }),
+        };
+        assert_eq!(gap.verify::<Tagged, RawBytes, ByValue, _>("serial 015", tree.len(), tree.root()), Err(VerificationError::WrongIndex));
+
+        let last = ExclusionProof { lower: tree.prove_absence::<Tagged, _>("serial 016").unwrap().lower, higher: None };
+        assert_eq!(last.verify::<Tagged, RawBytes, ByValue, _>("serial 099", tree.len(), tree.root()), Err(VerificationError::WrongIndex));
+    }
+}
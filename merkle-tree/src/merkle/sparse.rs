@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable};
+
+use super::{node::Node, combiner::NodeCombiner, MerkleError, VerificationError};
+
+/// Levels below the root, one for every bit of a key
+pub const DEPTH: usize = 256;
+
+/// Merkle tree with a leaf for every possible 256 bit key, most of them empty.
+///
+/// The bits of the key, from the most significant one, are the path from the root:
+/// keys are sorted as big endian numbers and, like in every tree, the right child
+/// holds the lower ones. Empty leaves hash to the null leaf, so an empty subtree
+/// hashes to a value that only depends on its height, and only the nodes with
+/// some key below them are stored.
+pub struct SparseMerkleTree<T: Hashable>{
+    values: HashMap<Vec<u8>, T>,
+    /// Hashes of non empty nodes, by depth and the bits of the key above that depth
+    nodes: HashMap<(usize, Vec<u8>), CryptoHash>,
+    /// Hash of an empty subtree, by height
+    empties: Vec<CryptoHash>,
+}
+
+impl<T: Hashable> SparseMerkleTree<T> {
+    /// Empty tree, `H` and `C` must be the same ones every other call uses
+    pub fn new<H: CryptoHasher, C: NodeCombiner>() -> Self{
+        Self { values: HashMap::new(), nodes: HashMap::new(), empties: empties::<H,C>() }
+    }
+
+    pub fn root(&self) -> &CryptoHash{
+        self.nodes.get(&(0, vec![0; DEPTH / 8])).unwrap_or(&self.empties[DEPTH])
+    }
+
+    /// Amount of keys with a value
+    pub fn len(&self) -> usize{
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.values.is_empty()
+    }
+
+    pub fn get(&self, key: &CryptoHash) -> Option<&T>{
+        self.values.get(&key.data)
+    }
+
+    /// Set the value of `key`, returning the one it had
+    pub fn insert<H: CryptoHasher, C: NodeCombiner>(&mut self, key: &CryptoHash, value: T) -> Result<Option<T>, MerkleError>{
+        check(key)?;
+        self.set::<H,C>(&key.data, Some(Node::hash_leaf::<H, _>(&value)));
+        Ok(self.values.insert(key.data.clone(), value))
+    }
+
+    /// Leave `key` empty, returning the value it had
+    pub fn delete<H: CryptoHasher, C: NodeCombiner>(&mut self, key: &CryptoHash) -> Result<Option<T>, MerkleError>{
+        check(key)?;
+        let old = self.values.remove(&key.data);
+        if old.is_some() {
+            self.set::<H,C>(&key.data, None);
+        }
+
+        Ok(old)
+    }
+
+    /// Proof of the value of `key`, or that it has none
+    pub fn prove(&self, key: &CryptoHash) -> Result<SparseProof, MerkleError>{
+        check(key)?;
+        let siblings = (1..=DEPTH).rev()
+            .map(|depth| self.nodes.get(&(depth, sibling(&key.data, depth))).cloned())
+            .collect();
+
+        Ok(SparseProof { siblings })
+    }
+
+    /// Hash again the path from the leaf of `key` to the root, `None` empties the leaf
+    fn set<H: CryptoHasher, C: NodeCombiner>(&mut self, key: &[u8], leaf: Option<CryptoHash>){
+        let mut current = leaf.unwrap_or_else(|| self.empties[0].clone());
+        for depth in (0..=DEPTH).rev() {
+            let height = DEPTH - depth;
+            let prefix = prefix(key, depth);
+            if current == self.empties[height] {
+                self.nodes.remove(&(depth, prefix));
+            }else{
+                self.nodes.insert((depth, prefix), current.clone());
+            }
+
+            if depth == 0 {
+                break;
+            }
+            let sibling = self.nodes.get(&(depth, sibling(key, depth))).unwrap_or(&self.empties[height]);
+            current = parent::<H,C>(key, depth, &current, sibling);
+        }
+    }
+}
+
+/// Proof of the value of a key in a `SparseMerkleTree`, or that it has none
+#[derive(Clone)]
+pub struct SparseProof{
+    /// From the leaf up, `None` for empty subtrees
+    pub(crate) siblings: Vec<Option<CryptoHash>>,
+}
+
+impl SparseProof {
+    pub fn siblings(&self) -> &[Option<CryptoHash>]{
+        &self.siblings
+    }
+
+    /// Check that `key` has `value` in the tree whose root is `root`.
+    ///
+    /// `H` and `C` must be the same ones the tree was built with.
+    pub fn verify_membership<H: CryptoHasher, C: NodeCombiner, V: Hashable + ?Sized>(&self, key: &CryptoHash, value: &V, root: &CryptoHash) -> Result<(), VerificationError>{
+        self.verify::<H,C>(key, Node::hash_leaf::<H, _>(value), root)
+    }
+
+    /// Check that `key` has no value in the tree whose root is `root`
+    pub fn verify_non_membership<H: CryptoHasher, C: NodeCombiner>(&self, key: &CryptoHash, root: &CryptoHash) -> Result<(), VerificationError>{
+        self.verify::<H,C>(key, Node::null::<H>(), root)
+    }
+
+    fn verify<H: CryptoHasher, C: NodeCombiner>(&self, key: &CryptoHash, leaf: CryptoHash, root: &CryptoHash) -> Result<(), VerificationError>{
+        if key.data.len() != DEPTH / 8 || self.siblings.len() != DEPTH {
+            return Err(VerificationError::MalformedProof);
+        }
+
+        let empties = empties::<H,C>();
+        let mut current = leaf;
+        for (height, sibling) in self.siblings.iter().enumerate() {
+            let sibling = sibling.as_ref().unwrap_or(&empties[height]);
+            current = parent::<H,C>(&key.data, DEPTH - height, &current, sibling);
+        }
+
+        if current != *root {
+            return Err(VerificationError::WrongRoot);
+        }
+
+        Ok(())
+    }
+}
+
+fn check(key: &CryptoHash) -> Result<(), MerkleError>{
+    if key.data.len() != DEPTH / 8 {
+        return Err(MerkleError::InvalidKey { len: key.data.len() });
+    }
+
+    Ok(())
+}
+
+/// Hashes of empty subtrees, from a single null leaf up to the whole tree
+fn empties<H: CryptoHasher, C: NodeCombiner>() -> Vec<CryptoHash>{
+    let mut empties = vec![Node::null::<H>()];
+    for height in 1..=DEPTH {
+        empties.push(C::combine::<H>(&empties[height - 1], &empties[height - 1]));
+    }
+
+    empties
+}
+
+fn bit(key: &[u8], index: usize) -> bool{
+    key[index / 8] & (0x80 >> (index % 8)) != 0
+}
+
+/// The first `depth` bits of `key`, the rest set to zero
+fn prefix(key: &[u8], depth: usize) -> Vec<u8>{
+    let mut prefix = key.to_vec();
+    for index in depth..DEPTH {
+        prefix[index / 8] &= !(0x80 >> (index % 8));
+    }
+
+    prefix
+}
+
+/// Prefix of the other child of the parent of the node of `key` at `depth`
+fn sibling(key: &[u8], depth: usize) -> Vec<u8>{
+    let mut sibling = prefix(key, depth);
+    sibling[(depth - 1) / 8] ^= 0x80 >> ((depth - 1) % 8);
+    sibling
+}
+
+/// Hash of the parent of the node of `key` at `depth`, whose sibling is `sibling`
+fn parent<H: CryptoHasher, C: NodeCombiner>(key: &[u8], depth: usize, current: &CryptoHash, sibling: &CryptoHash) -> CryptoHash{
+    if bit(key, depth - 1) {
+        C::combine::<H>(current, sibling)
+    }else{
+        C::combine::<H>(sibling, current)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::{sha256::SHA256, separated::{Separated, ByteTags}}, merkle::combiner::RawBytes};
+
+    use super::*;
+
+    type Tagged = Separated<SHA256, ByteTags>;
+
+    fn key(i: usize) -> CryptoHash{
+        SHA256::hash(format!("key number {}", i).as_bytes())
+    }
+
+    fn value(i: usize) -> String{
+        format!("value number {}", i)
+    }
+
+    #[test]
+    fn insert_get_delete(){
+        let mut tree = SparseMerkleTree::new::<Tagged, RawBytes>();
+        let empty = tree.root().clone();
+
+        for i in 0..20 {
+            assert_eq!(tree.insert::<Tagged, RawBytes>(&key(i), value(i)), Ok(None));
+        }
+        assert_eq!(tree.len(), 20);
+        assert_eq!(tree.get(&key(3)), Some(&value(3)));
+        assert_eq!(tree.get(&key(20)), None);
+        assert_eq!(tree.insert::<Tagged, RawBytes>(&key(3), value(33)), Ok(Some(value(3))));
+        assert_eq!(tree.get(&key(3)), Some(&value(33)));
+
+        // The root only depends on the contents, not on the order they were set in
+        let mut other = SparseMerkleTree::new::<Tagged, RawBytes>();
+        for i in (0..20).rev() {
+            let value = if i == 3 { value(33) } else { value(i) };
+            other.insert::<Tagged, RawBytes>(&key(i), value).unwrap();
+        }
+        assert_eq!(other.root().data, tree.root().data);
+
+        assert_eq!(tree.delete::<Tagged, RawBytes>(&key(20)), Ok(None));
+        for i in 0..20 {
+            assert!(tree.delete::<Tagged, RawBytes>(&key(i)).unwrap().is_some());
+        }
+        assert!(tree.is_empty());
+        assert!(tree.nodes.is_empty());
+        assert_eq!(tree.root().data, empty.data);
+    }
+
+    #[test]
+    fn membership_proofs(){
+        let mut tree = SparseMerkleTree::new::<Tagged, RawBytes>();
+        for i in 0..10 {
+            tree.insert::<Tagged, RawBytes>(&key(i), value(i)).unwrap();
+        }
+
+        for i in 0..10 {
+            let proof = tree.prove(&key(i)).unwrap();
+            assert_eq!(proof.verify_membership::<Tagged, RawBytes, _>(&key(i), &value(i), tree.root()), Ok(()));
+            assert_eq!(proof.verify_membership::<Tagged, RawBytes, _>(&key(i), &value(i + 1), tree.root()), Err(VerificationError::WrongRoot));
+            assert_eq!(proof.verify_non_membership::<Tagged, RawBytes>(&key(i), tree.root()), Err(VerificationError::WrongRoot));
+        }
+    }
+
+    #[test]
+    fn non_membership_proofs(){
+        let mut tree = SparseMerkleTree::new::<Tagged, RawBytes>();
+        let proof = tree.prove(&key(0)).unwrap();
+        assert_eq!(proof.verify_non_membership::<Tagged, RawBytes>(&key(0), tree.root()), Ok(()));
+
+        for i in 0..10 {
+            tree.insert::<Tagged, RawBytes>(&key(i), value(i)).unwrap();
+        }
+        let proof = tree.prove(&key(10)).unwrap();
+        assert_eq!(proof.verify_non_membership::<Tagged, RawBytes>(&key(10), tree.root()), Ok(()));
+        assert_eq!(proof.verify_non_membership::<Tagged, RawBytes>(&key(3), tree.root()), Err(VerificationError::WrongRoot));
+
+        let mut truncated = proof.clone();
+        truncated.siblings.pop();
+        assert_eq!(truncated.verify_non_membership::<Tagged, RawBytes>(&key(10), tree.root()), Err(VerificationError::MalformedProof));
+    }
+
+    #[test]
+    fn keys_are_256_bits(){
+        let mut tree = SparseMerkleTree::new::<Tagged, RawBytes>();
+        let short = CryptoHash { data: vec![1; 20] };
+        assert_eq!(tree.insert::<Tagged, RawBytes>(&short, value(0)), Err(MerkleError::InvalidKey { len: 20 }));
+        assert!(tree.prove(&short).is_err());
+        assert!(tree.get(&short).is_none());
+    }
+}
@@ -0,0 +1,216 @@
+use std::{collections::HashMap, fs::{File, OpenOptions}, io::{self, BufReader, Read, Seek, SeekFrom, Write}, path::Path};
+
+use crate::hashers::CryptoHash;
+
+use super::{Children, NodeStore, super::{persist::{PersistError, read_exact_at}, DecodeError}};
+
+const MAGIC: &[u8; 4] = b"MRKN";
+const VERSION: u8 = 1;
+
+/// Nodes appended to a file, nothing written is ever changed.
+///
+/// Every record is the node hash and then both children, each after a byte
+/// with its length. Only where every node starts is kept in memory, its
+/// children are read from the file when asked for. Removing a node appends
+/// a record without children, the space it took is not reclaimed.
+///
+/// A record with an empty hash holds the roots of every version instead,
+/// the last one replaces the ones before it.
+pub struct FileStore{
+    file: File,
+    /// Where the children of every node start in the file
+    offsets: HashMap<Vec<u8>, u64>,
+    versions: Vec<(usize, CryptoHash)>,
+    end: u64,
+}
+
+/// What a record of the file holds
+enum Record{
+    Node { hash: Vec<u8>, children: u64 },
+    Removed(Vec<u8>),
+    Versions(Vec<(usize, CryptoHash)>),
+}
+
+impl FileStore {
+    /// Open the store at `path`, creating it if there is none.
+    ///
+    /// A record cut short by a crash while appending is dropped.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, PersistError>{
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let len = file.metadata()?.len();
+        if len == 0 {
+            file.write_all(MAGIC)?;
+            file.write_all(&[VERSION])?;
+            return Ok(Self { file, offsets: HashMap::new(), versions: Vec::new(), end: MAGIC.len() as u64 + 1 });
+        }
+
+        let mut reader = BufReader::new(&file);
+        let mut header = [0u8; 5];
+        if reader.read_exact(&mut header).is_err() || &header[..MAGIC.len()] != MAGIC {
+            return Err(PersistError::NotATree);
+        }
+        if header[MAGIC.len()] != VERSION {
+            return Err(DecodeError::UnsupportedVersion(header[MAGIC.len()]).into());
+        }
+
+        let mut offsets = HashMap::new();
+        let mut versions = Vec::new();
+        let mut end = header.len() as u64;
+        loop {
+            let record = (|| -> io::Result<Record> {
+                let hash = field(&mut reader)?;
+                if hash.data.is_empty() {
+                    let count = u64_field(&mut reader)?;
+                    let versions = (0..count)
+                        .map(|_| {
+                            let version = usize::try_from(u64_field(&mut reader)?).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+                            Ok((version, field(&mut reader)?))
+                        })
+                        .collect::<io::Result<_>>()?;
+                    return Ok(Record::Versions(versions));
+                }
+
+                let left = field(&mut reader)?;
+                let right = field(&mut reader)?;
+                match left.data.len() + right.data.len() {
+                    0 => Ok(Record::Removed(hash.data)),
+                    children => Ok(Record::Node { hash: hash.data, children: children as u64 + 2 }),
+                }
+            })();
+
+            match record {
+                Ok(Record::Removed(hash)) => {
+                    end += 1 + hash.len() as u64 + 2;
+                    offsets.remove(&hash);
+                },
+                Ok(Record::Node { hash, children }) => {
+                    let children_start = end + 1 + hash.len() as u64;
+                    offsets.insert(hash, children_start);
+                    end = children_start + children;
+                },
+                Ok(Record::Versions(read)) => {
+                    end += 1 + 8 + read.iter().map(|(_, root)| 8 + 1 + root.data.len() as u64).sum::<u64>();
+                    versions = read;
+                },
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        if end < len {
+            file.set_len(end)?;
+        }
+
+        Ok(Self { file, offsets, versions, end })
+    }
+
+    /// Write `records` at the end of the file
+    fn append(&mut self, records: &[u8]) -> Result<(), PersistError>{
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(records)?;
+        self.end += records.len() as u64;
+        Ok(())
+    }
+}
+
+/// Hash stored after a byte with its length
+fn field(mut reader: impl Read) -> io::Result<CryptoHash>{
+    let mut len = [0u8];
+    reader.read_exact(&mut len)?;
+    let mut data = vec![0u8; len[0] as usize];
+    reader.read_exact(&mut data)?;
+    Ok(CryptoHash { data })
+}
+
+fn u64_field(mut reader: impl Read) -> io::Result<u64>{
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn push_field(record: &mut Vec<u8>, field: &CryptoHash) -> Result<(), PersistError>{
+    let len = u8::try_from(field.data.len()).map_err(|_| PersistError::HashTooLong(field.data.len()))?;
+    record.push(len);
+    record.extend_from_slice(&field.data);
+    Ok(())
+}
+
+/// Hash stored after a byte with its length, starting at `offset`
+fn field_at(file: &File, offset: u64) -> io::Result<CryptoHash>{
+    let mut len = [0u8];
+    read_exact_at(file, &mut len, offset)?;
+    let mut data = vec![0u8; len[0] as usize];
+    read_exact_at(file, &mut data, offset + 1)?;
+    Ok(CryptoHash { data })
+}
+
+impl NodeStore for FileStore {
+    fn get(&self, hash: &CryptoHash) -> Result<Option<Children>, PersistError> {
+        let offset = match self.offsets.get(&hash.data) {
+            Some(offset) => *offset,
+            None => return Ok(None),
+        };
+
+        let left = field_at(&self.file, offset)?;
+        let right = field_at(&self.file, offset + 1 + left.data.len() as u64)?;
+        Ok(Some(Children { left, right }))
+    }
+
+    fn put(&mut self, hash: CryptoHash, children: Children) -> Result<(), PersistError> {
+        self.put_many(vec![(hash, children)])
+    }
+
+    /// All the records are appended with a single write
+    fn put_many(&mut self, nodes: Vec<(CryptoHash, Children)>) -> Result<(), PersistError> {
+        let mut records = Vec::new();
+        let mut offsets = HashMap::new();
+        for (hash, children) in nodes {
+            if self.offsets.contains_key(&hash.data) || offsets.contains_key(&hash.data) {
+                continue;
+            }
+
+            push_field(&mut records, &hash)?;
+            offsets.insert(hash.data, self.end + records.len() as u64);
+            push_field(&mut records, &children.left)?;
+            push_field(&mut records, &children.right)?;
+        }
+
+        self.append(&records)?;
+        self.offsets.extend(offsets);
+        Ok(())
+    }
+
+    fn remove(&mut self, hash: &CryptoHash) -> Result<(), PersistError> {
+        if !self.offsets.contains_key(&hash.data) {
+            return Ok(());
+        }
+
+        let mut record = Vec::with_capacity(hash.data.len() + 3);
+        push_field(&mut record, hash)?;
+        record.extend([0, 0]);
+        self.append(&record)?;
+        self.offsets.remove(&hash.data);
+        Ok(())
+    }
+
+    fn versions(&self) -> Result<Vec<(usize, CryptoHash)>, PersistError> {
+        Ok(self.versions.clone())
+    }
+
+    fn set_versions(&mut self, versions: Vec<(usize, CryptoHash)>) -> Result<(), PersistError> {
+        let mut record = vec![0];
+        record.extend((versions.len() as u64).to_be_bytes());
+        for (version, root) in &versions {
+            record.extend((*version as u64).to_be_bytes());
+            push_field(&mut record, root)?;
+        }
+
+        self.append(&record)?;
+        self.versions = versions;
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+}
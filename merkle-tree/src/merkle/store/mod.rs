@@ -0,0 +1,96 @@
+pub mod file;
+pub mod tree;
+pub mod versioned;
+
+use std::collections::HashMap;
+
+use crate::hashers::CryptoHash;
+
+use super::persist::PersistError;
+
+/// Hashes of the children of an interior node, the right one holds the lower indices
+#[derive(Clone)]
+pub struct Children{
+    pub left: CryptoHash,
+    pub right: CryptoHash,
+}
+
+/// Where the interior nodes of trees are kept, by their hash.
+///
+/// The same node is stored once no matter how many trees have it, so versions
+/// of a tree share every subtree that did not change. Leaves are not stored,
+/// their hash is all there is to them.
+pub trait NodeStore{
+    /// Children of the node whose hash is `hash`, `None` if it was never put
+    fn get(&self, hash: &CryptoHash) -> Result<Option<Children>, PersistError>;
+
+    /// Keep the children of `hash`, nothing changes if it is already there
+    fn put(&mut self, hash: CryptoHash, children: Children) -> Result<(), PersistError>;
+
+    /// Keep several nodes at once
+    fn put_many(&mut self, nodes: Vec<(CryptoHash, Children)>) -> Result<(), PersistError>{
+        for (hash, children) in nodes {
+            self.put(hash, children)?;
+        }
+
+        Ok(())
+    }
+
+    /// Forget the node whose hash is `hash`, nothing changes if it is not there
+    fn remove(&mut self, hash: &CryptoHash) -> Result<(), PersistError>;
+
+    /// Roots of the versions of a `VersionedMerkleTree` kept along with the nodes, empty if there are none
+    fn versions(&self) -> Result<Vec<(usize, CryptoHash)>, PersistError>;
+
+    /// Replace the roots `versions` returns
+    fn set_versions(&mut self, versions: Vec<(usize, CryptoHash)>) -> Result<(), PersistError>;
+
+    /// Amount of nodes kept
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool{
+        self.len() == 0
+    }
+}
+
+/// Nodes kept in a map, lost with it
+#[derive(Default)]
+pub struct MemoryStore{
+    nodes: HashMap<Vec<u8>, Children>,
+    versions: Vec<(usize, CryptoHash)>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self{
+        Self::default()
+    }
+}
+
+impl NodeStore for MemoryStore {
+    fn get(&self, hash: &CryptoHash) -> Result<Option<Children>, PersistError> {
+        Ok(self.nodes.get(&hash.data).cloned())
+    }
+
+    fn put(&mut self, hash: CryptoHash, children: Children) -> Result<(), PersistError> {
+        self.nodes.entry(hash.data).or_insert(children);
+        Ok(())
+    }
+
+    fn remove(&mut self, hash: &CryptoHash) -> Result<(), PersistError> {
+        self.nodes.remove(&hash.data);
+        Ok(())
+    }
+
+    fn versions(&self) -> Result<Vec<(usize, CryptoHash)>, PersistError> {
+        Ok(self.versions.clone())
+    }
+
+    fn set_versions(&mut self, versions: Vec<(usize, CryptoHash)>) -> Result<(), PersistError> {
+        self.versions = versions;
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+}
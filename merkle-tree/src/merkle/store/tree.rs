@@ -0,0 +1,236 @@
+use std::{borrow::Cow, marker::PhantomData};
+
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable, digest::HashOutput};
+
+use super::{Children, NodeStore, super::{merkle_tree::MerkleTree, merkle_trace::MerkleTrace, combiner::NodeCombiner, walk::{self, TreeNodes}, persist::PersistError, TreeShape, MerkleError}};
+
+/// Merkle tree whose interior nodes are in a `NodeStore`, found from the root by their hashes.
+///
+/// Updates put the nodes of the new path in the store and leave the old ones,
+/// so the tree before the update can still be reached from its root.
+pub struct StoredMerkleTree<T: Hashable, S: NodeStore>{
+    store: S,
+    root: CryptoHash,
+    original_len: usize,
+    shape: TreeShape,
+    src: PhantomData<fn() -> T>
+}
+
+impl<T: Hashable, S: NodeStore> StoredMerkleTree<T, S> {
+    /// Put every interior node of `tree` in `store`.
+    ///
+    /// `H` and `C` must be the same ones the tree was built with, and the ones used to update it.
+    pub fn from_tree<D: HashOutput>(tree: &MerkleTree<T, D>, mut store: S) -> Result<Self, StoreTreeError>{
+        for level in 1..=tree.depth() {
+            let nodes = (0..tree.width(level))
+                .map(|position| {
+                    let children = Children {
+                        left: tree.hash_at(level - 1, position * 2 + 1)?.as_hash().into_owned(),
+                        right: tree.hash_at(level - 1, position * 2)?.as_hash().into_owned(),
+                    };
+                    Ok((tree.hash_at(level, position)?.as_hash().into_owned(), children))
+                })
+                .collect::<Result<_, MerkleError>>()?;
+            store.put_many(nodes)?;
+        }
+
+        Ok(Self::from_root(store, tree.root().as_hash().into_owned(), tree.len(), tree.shape()))
+    }
+
+    /// Tree whose nodes are already in `store`, like the ones of an older root
+    pub fn from_root(store: S, root: CryptoHash, len: usize, shape: TreeShape) -> Self{
+        Self { store, root, original_len: len, shape, src: PhantomData }
+    }
+
+    pub fn root(&self) -> &CryptoHash{
+        &self.root
+    }
+
+    pub fn len(&self) -> usize{
+        self.original_len
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.original_len == 0
+    }
+
+    pub fn shape(&self) -> TreeShape{
+        self.shape
+    }
+
+    pub fn store(&self) -> &S{
+        &self.store
+    }
+
+    pub fn into_store(self) -> S{
+        self.store
+    }
+
+    /// Levels below the root
+    pub fn depth(&self) -> usize{
+        walk::depth(self.original_len)
+    }
+
+    pub fn generate_trace(&self, which: usize) -> Result<MerkleTrace, StoreTreeError>{
+        trace(&self.store, &self.root, which, self.original_len, self.shape)
+    }
+
+    /// Replace the leaf at `index`, only the nodes of the new path are put in the store.
+    ///
+    /// `H` and `C` must be the same ones the tree was built with.
+    pub fn update<H: CryptoHasher, C: NodeCombiner>(&mut self, index: usize, value: &T) -> Result<(), StoreTreeError>{
+        self.root = update::<H, C, _, _>(&mut self.store, &self.root, index, value, self.original_len, self.shape)?;
+        Ok(())
+    }
+}
+
+/// Why a tree in a `NodeStore` could not be read or changed
+#[derive(Debug)]
+pub enum StoreTreeError{
+    Tree(MerkleError),
+    Store(PersistError),
+    /// A node the tree reaches is not in the store
+    MissingNode,
+    /// A `VersionedMerkleTree` needs at least one version
+    NoVersions,
+}
+
+impl From<MerkleError> for StoreTreeError {
+    fn from(error: MerkleError) -> Self {
+        Self::Tree(error)
+    }
+}
+
+impl From<PersistError> for StoreTreeError {
+    fn from(error: PersistError) -> Self {
+        Self::Store(error)
+    }
+}
+
+/// The nodes of the tree under `root`, read from `store` as the walk reaches them
+struct StoredNodes<'a, S: NodeStore>{
+    store: &'a S,
+    root: &'a CryptoHash,
+}
+
+impl<S: NodeStore> TreeNodes for StoredNodes<'_, S> {
+    type Hash = CryptoHash;
+    type Node = CryptoHash;
+    type Error = StoreTreeError;
+
+    fn root_node(&self) -> CryptoHash {
+        self.root.clone()
+    }
+
+    fn hash_of<'a>(&'a self, node: &'a CryptoHash) -> Result<Cow<'a, CryptoHash>, StoreTreeError> {
+        Ok(Cow::Borrowed(node))
+    }
+
+    fn children(&self, hash: &CryptoHash) -> Result<(CryptoHash, CryptoHash), StoreTreeError> {
+        let Children { left, right } = self.store.get(hash)?.ok_or(StoreTreeError::MissingNode)?;
+        Ok((left, right))
+    }
+}
+
+/// Trace of the leaf at `which` of the tree under `root`, walking down from it
+pub(crate) fn trace<S: NodeStore>(store: &S, root: &CryptoHash, which: usize, len: usize, shape: TreeShape) -> Result<MerkleTrace, StoreTreeError>{
+    walk::trace(&StoredNodes { store, root }, which, len, shape)
+}
+
+/// Root of the tree under `root` with the leaf at `index` replaced by `value`
+pub(crate) fn update<H, C, T, S>(store: &mut S, root: &CryptoHash, index: usize, value: &T, len: usize, shape: TreeShape) -> Result<CryptoHash, StoreTreeError>
+where H: CryptoHasher, C: NodeCombiner, T: Hashable + ?Sized, S: NodeStore
+{
+    let leaves = walk::changed_leaves::<H, CryptoHash, T>(&[(index, value)], len, shape)?;
+    let mut changed = Vec::new();
+    let root = walk::apply::<H, C, _>(&StoredNodes { store: &*store, root }, &leaves, len, shape, &mut changed)?;
+
+    let nodes = changed.into_iter()
+        .map(|node| (node.hash, Children { left: node.left, right: node.right }))
+        .collect();
+    store.put_many(nodes)?;
+    Ok(root)
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, encoding::hex::Hex, merkle::{combiner::RawBytes, store::{MemoryStore, file::FileStore}}};
+
+    use crate::merkle::testing::{data, shapes, path};
+    use super::*;
+
+    #[test]
+    fn same_traces_and_updates_as_in_memory(){
+        let data = data(11);
+        for shape in shapes() {
+            let mut tree = MerkleTree::from_data::<SHA256, Hex>(&data, shape);
+            let mut stored = StoredMerkleTree::from_tree(&tree, MemoryStore::new()).unwrap();
+            for (i, leaf) in data.iter().enumerate() {
+                let trace = stored.generate_trace(i).unwrap();
+                assert_eq!(trace.verify::<SHA256, Hex, _>(leaf, i, tree.root()), Ok(()), "{} in {:?}", i, shape);
+            }
+
+            for i in [0, 5, 10] {
+                let changed = format!("changed {}", i);
+                tree.update::<SHA256, Hex>(i, &changed).unwrap();
+                stored.update::<SHA256, Hex>(i, &changed).unwrap();
+                assert_eq!(stored.root().data, tree.root().data, "{} in {:?}", i, shape);
+                assert_eq!(stored.generate_trace(i).unwrap().verify::<SHA256, Hex, _>(&changed, i, tree.root()), Ok(()));
+            }
+            assert!(matches!(stored.generate_trace(11), Err(StoreTreeError::Tree(MerkleError::IndexOutOfBounds { index: 11, len: 11 }))));
+        }
+    }
+
+    #[test]
+    fn versions_share_unchanged_subtrees(){
+        let data = data(16);
+        let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data, TreeShape::FullNullExtend);
+        let mut stored = StoredMerkleTree::from_tree(&tree, MemoryStore::new()).unwrap();
+        assert_eq!(stored.store().len(), 15);
+
+        let old_root = stored.root().clone();
+        stored.update::<SHA256, RawBytes>(6, &"changed".to_string()).unwrap();
+        // Only the four nodes on the path are new
+        assert_eq!(stored.store().len(), 19);
+
+        let old = StoredMerkleTree::<String, _>::from_root(stored.into_store(), old_root, 16, TreeShape::FullNullExtend);
+        assert_eq!(old.generate_trace(6).unwrap().verify::<SHA256, RawBytes, _>(&data[6], 6, tree.root()), Ok(()));
+    }
+
+    #[test]
+    fn file_store_reopens(){
+        let path = path("reopen.nodes");
+        let _ = std::fs::remove_file(&path);
+        let data = data(9);
+        let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data, TreeShape::PartialCopyExtend);
+
+        let mut stored = StoredMerkleTree::from_tree(&tree, FileStore::open(&path).unwrap()).unwrap();
+        stored.update::<SHA256, RawBytes>(8, &"changed".to_string()).unwrap();
+        let (root, nodes) = (stored.root().clone(), stored.store().len());
+        drop(stored);
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(), nodes);
+        let reopened = StoredMerkleTree::<String, _>::from_root(store, root.clone(), 9, TreeShape::PartialCopyExtend);
+        assert_eq!(reopened.generate_trace(8).unwrap().verify::<SHA256, RawBytes, str>("changed", 8, &root), Ok(()));
+        assert_eq!(reopened.generate_trace(2).unwrap().verify::<SHA256, RawBytes, _>(&data[2], 2, &root), Ok(()));
+
+        // A record cut short is dropped, along with nothing else
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(), nodes - 1);
+        assert_eq!(std::fs::metadata(&path).unwrap().len() as usize, bytes.len() - (1 + 32) * 3);
+
+        // Lengths are kept in a byte
+        let mut store = store;
+        let long = CryptoHash { data: vec![1; 256] };
+        let children = Children { left: long.clone(), right: long.clone() };
+        assert!(matches!(store.put(long, children), Err(PersistError::HashTooLong(256))));
+        assert_eq!(store.len(), nodes - 1);
+
+        std::fs::write(&path, b"not a store").unwrap();
+        assert!(matches!(FileStore::open(&path), Err(PersistError::NotATree)));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+use std::{collections::{BTreeMap, HashSet}, marker::PhantomData};
+
+use crate::hashers::{CryptoHash, CryptoHasher, Hashable, digest::HashOutput};
+
+use super::{Children, NodeStore, tree::{trace, update, StoreTreeError, StoredMerkleTree}, super::{merkle_tree::MerkleTree, merkle_trace::MerkleTrace, combiner::NodeCombiner, walk, TreeShape, MerkleError}};
+
+/// Every version a tree went through, each one found by its number.
+///
+/// Every update makes a new version, whose root only adds the nodes of the
+/// changed path to the store: the rest is shared with the version before it.
+/// Old versions serve proofs until they are pruned. The root of every version
+/// is kept in the store too, so the tree can be opened from it again.
+pub struct VersionedMerkleTree<T: Hashable, S: NodeStore>{
+    store: S,
+    /// Root of every version that was not pruned
+    roots: BTreeMap<usize, CryptoHash>,
+    original_len: usize,
+    shape: TreeShape,
+    src: PhantomData<fn() -> T>
+}
+
+impl<T: Hashable, S: NodeStore> VersionedMerkleTree<T, S> {
+    /// Version 0 is `tree`, whose nodes are put in `store`.
+    ///
+    /// `H` and `C` must be the same ones the tree was built with, and the ones used to update it.
+    pub fn new<D: HashOutput>(tree: &MerkleTree<T, D>, store: S) -> Result<Self, StoreTreeError>{
+        let stored = StoredMerkleTree::from_tree(tree, store)?;
+        let roots = BTreeMap::from([(0, stored.root().clone())]);
+        Self::from_roots(stored.into_store(), roots, tree.len(), tree.shape())
+    }
+
+    /// Versions whose nodes are already in `store`, like the ones listed by `versions` before.
+    ///
+    /// They replace the ones kept in the store. Fails if there are none.
+    pub fn from_versions(store: S, versions: impl IntoIterator<Item = (usize, CryptoHash)>, len: usize, shape: TreeShape) -> Result<Self, StoreTreeError>{
+        Self::from_roots(store, versions.into_iter().collect(), len, shape)
+    }
+
+    /// Versions kept in `store` by a tree with `len` leaves and the same `shape`.
+    ///
+    /// Fails if the store has none.
+    pub fn open(store: S, len: usize, shape: TreeShape) -> Result<Self, StoreTreeError>{
+        let roots = store.versions()?.into_iter().collect();
+        Self::from_roots(store, roots, len, shape)
+    }
+
+    fn from_roots(mut store: S, roots: BTreeMap<usize, CryptoHash>, len: usize, shape: TreeShape) -> Result<Self, StoreTreeError>{
+        if roots.is_empty() {
+            return Err(StoreTreeError::NoVersions);
+        }
+
+        store.set_versions(listed(&roots))?;
+        Ok(Self { store, roots, original_len: len, shape, src: PhantomData })
+    }
+
+    pub fn len(&self) -> usize{
+        self.original_len
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.original_len == 0
+    }
+
+    pub fn shape(&self) -> TreeShape{
+        self.shape
+    }
+
+    pub fn store(&self) -> &S{
+        &self.store
+    }
+
+    pub fn into_store(self) -> S{
+        self.store
+    }
+
+    /// Number of the newest version, the one updates start from
+    pub fn latest(&self) -> usize{
+        *self.roots.keys().next_back().expect("The newest version is never pruned")
+    }
+
+    /// Root of `version`, `None` if there is no such version or it was pruned
+    pub fn root(&self, version: usize) -> Option<&CryptoHash>{
+        self.roots.get(&version)
+    }
+
+    /// Every version that was not pruned with its root, the oldest first
+    pub fn versions(&self) -> impl Iterator<Item = (usize, &CryptoHash)>{
+        self.roots.iter().map(|(version, root)| (*version, root))
+    }
+
+    /// Trace of the leaf at `which` as it was in `version`
+    pub fn generate_trace(&self, version: usize, which: usize) -> Result<MerkleTrace, StoreTreeError>{
+        let root = self.roots.get(&version).ok_or(MerkleError::UnknownVersion { version })?;
+        trace(&self.store, root, which, self.original_len, self.shape)
+    }
+
+    /// Replace the leaf at `index` of the newest version, which makes a new one. Returns its number
+    pub fn update<H: CryptoHasher, C: NodeCombiner>(&mut self, index: usize, value: &T) -> Result<usize, StoreTreeError>{
+        let latest = self.latest();
+        let root = update::<H, C, _, _>(&mut self.store, &self.roots[&latest], index, value, self.original_len, self.shape)?;
+
+        let mut versions = listed(&self.roots);
+        versions.push((latest + 1, root.clone()));
+        self.store.set_versions(versions)?;
+        self.roots.insert(latest + 1, root);
+        Ok(latest + 1)
+    }
+
+    /// Drop every version older than `version`, and the nodes only they had.
+    ///
+    /// The newest version is always kept. No reference counts are kept, so every
+    /// call walks all the nodes the kept versions reach, the shared ones once:
+    /// it takes at least as long as walking the whole newest tree, however few
+    /// versions are dropped. Pruning many versions at a time is cheaper.
+    pub fn prune(&mut self, version: usize) -> Result<(), StoreTreeError>{
+        let version = version.min(self.latest());
+        let kept = self.roots.split_off(&version);
+        self.store.set_versions(listed(&kept))?;
+        let pruned = std::mem::replace(&mut self.roots, kept);
+
+        let depth = walk::depth(self.original_len);
+        let mut live = HashSet::new();
+        for root in self.roots.values() {
+            self.walk(root, depth, &mut live, &mut |_| {})?;
+        }
+
+        let mut dead = Vec::new();
+        let mut visited = live;
+        for root in pruned.values() {
+            self.walk(root, depth, &mut visited, &mut |hash| dead.push(hash.clone()))?;
+        }
+        for hash in &dead {
+            self.store.remove(hash)?;
+        }
+
+        Ok(())
+    }
+
+    /// Visit every interior node under `hash` that is not in `visited`, adding them to it
+    fn walk(&self, hash: &CryptoHash, level: usize, visited: &mut HashSet<Vec<u8>>, visit: &mut impl FnMut(&CryptoHash)) -> Result<(), StoreTreeError>{
+        if level == 0 || !visited.insert(hash.data.clone()) {
+            return Ok(());
+        }
+
+        let Children { left, right } = self.store.get(hash)?.ok_or(StoreTreeError::MissingNode)?;
+        visit(hash);
+        self.walk(&right, level - 1, visited, visit)?;
+        self.walk(&left, level - 1, visited, visit)
+    }
+}
+
+/// Roots of every version, the oldest first
+fn listed(roots: &BTreeMap<usize, CryptoHash>) -> Vec<(usize, CryptoHash)>{
+    roots.iter().map(|(version, root)| (*version, root.clone())).collect()
+}
+
+#[cfg(test)]
+mod test{
+    use crate::{hashers::sha256::SHA256, merkle::{combiner::RawBytes, store::{MemoryStore, file::FileStore}}};
+
+    use crate::merkle::testing::{data, shapes, path};
+    use super::*;
+
+    #[test]
+    fn old_versions_serve_proofs(){
+        let mut data = data(10);
+        for shape in shapes() {
+            let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data, shape);
+            let mut versioned = VersionedMerkleTree::new(&tree, MemoryStore::new()).unwrap();
+
+            let mut snapshots = vec![(data.clone(), tree.root().clone())];
+            for (version, index) in [3, 9, 3, 0].into_iter().enumerate() {
+                data[index] = format!("version {}", version + 1);
+                assert_eq!(versioned.update::<SHA256, RawBytes>(index, &data[index]).unwrap(), version + 1);
+
+                let rebuilt = MerkleTree::from_data::<SHA256, RawBytes>(&data, shape);
+                assert_eq!(versioned.root(version + 1).unwrap().data, rebuilt.root().data, "{:?}", shape);
+                snapshots.push((data.clone(), rebuilt.root().clone()));
+            }
+
+            for (version, (leaves, root)) in snapshots.iter().enumerate() {
+                for (i, leaf) in leaves.iter().enumerate() {
+                    let trace = versioned.generate_trace(version, i).unwrap();
+                    assert_eq!(trace.verify::<SHA256, RawBytes, _>(leaf, i, root), Ok(()), "{} of version {} in {:?}", i, version, shape);
+                }
+            }
+            assert_eq!(versioned.latest(), 4);
+            assert!(matches!(versioned.generate_trace(5, 0), Err(StoreTreeError::Tree(MerkleError::UnknownVersion { version: 5 }))));
+        }
+    }
+
+    #[test]
+    fn pruning_drops_only_unshared_nodes(){
+        let data = data(16);
+        let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data, TreeShape::FullNullExtend);
+        let mut versioned = VersionedMerkleTree::new(&tree, MemoryStore::new()).unwrap();
+        versioned.update::<SHA256, RawBytes>(0, &"first".to_string()).unwrap();
+        versioned.update::<SHA256, RawBytes>(15, &"second".to_string()).unwrap();
+        // 15 nodes, and then the 4 of every changed path
+        assert_eq!(versioned.store().len(), 23);
+
+        versioned.prune(2).unwrap();
+        assert_eq!(versioned.versions().map(|(version, _)| version).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(versioned.store().len(), 15);
+        assert!(versioned.generate_trace(0, 1).is_err());
+        for (i, leaf) in data.iter().enumerate().take(15).skip(1) {
+            assert_eq!(versioned.generate_trace(2, i).unwrap().verify::<SHA256, RawBytes, _>(leaf, i, versioned.root(2).unwrap()), Ok(()));
+        }
+
+        // The newest version stays
+        versioned.prune(10).unwrap();
+        assert_eq!(versioned.latest(), 2);
+        assert_eq!(versioned.store().len(), 15);
+    }
+
+    #[test]
+    fn versions_survive_reopening_the_store(){
+        let path = path("versions.nodes");
+        let _ = std::fs::remove_file(&path);
+        let data = data(7);
+        let tree = MerkleTree::from_data::<SHA256, RawBytes>(&data, TreeShape::PartialNullExtend);
+
+        let mut versioned = VersionedMerkleTree::new(&tree, FileStore::open(&path).unwrap()).unwrap();
+        versioned.update::<SHA256, RawBytes>(6, &"changed".to_string()).unwrap();
+        versioned.update::<SHA256, RawBytes>(2, &"changed".to_string()).unwrap();
+        versioned.prune(1).unwrap();
+        let versions: Vec<(usize, CryptoHash)> = versioned.versions().map(|(version, root)| (version, root.clone())).collect();
+        let nodes = versioned.store().len();
+        drop(versioned);
+
+        // The versions were kept along with the nodes
+        let reopened = VersionedMerkleTree::<String, _>::open(FileStore::open(&path).unwrap(), 7, TreeShape::PartialNullExtend).unwrap();
+        assert_eq!(reopened.versions().map(|(version, root)| (version, root.clone())).collect::<Vec<_>>(), versions);
+        let reopened = VersionedMerkleTree::<String, _>::from_versions(reopened.into_store(), versions, 7, TreeShape::PartialNullExtend).unwrap();
+        assert_eq!(reopened.store().len(), nodes);
+        let root = reopened.root(1).unwrap();
+        assert_eq!(reopened.generate_trace(1, 6).unwrap().verify::<SHA256, RawBytes, str>("changed", 6, root), Ok(()));
+        assert_eq!(reopened.generate_trace(1, 2).unwrap().verify::<SHA256, RawBytes, _>(&data[2], 2, root), Ok(()));
+
+        let store = reopened.into_store();
+        assert!(matches!(VersionedMerkleTree::<String, _>::from_versions(store, [], 7, TreeShape::PartialNullExtend), Err(StoreTreeError::NoVersions)));
+        assert!(matches!(VersionedMerkleTree::<String, _>::open(MemoryStore::new(), 7, TreeShape::PartialNullExtend), Err(StoreTreeError::NoVersions)));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+//! Fixtures shared by the tests of every kind of tree
+
+use std::{alloc::{GlobalAlloc, Layout, System}, cell::Cell, path::PathBuf};
+
+use super::TreeShape;
+
+pub(crate) fn data(amount: usize) -> Vec<String>{
+    (0..amount).map(|i| format!("leaf number {}", i)).collect()
+}
+
+pub(crate) fn shapes() -> [TreeShape; 4]{
+    [TreeShape::FullCopyExtend, TreeShape::FullNullExtend, TreeShape::PartialCopyExtend, TreeShape::PartialNullExtend]
+}
+
+/// File in the temporary directory for `name`, not shared with other test runs
+pub(crate) fn path(name: &str) -> PathBuf{
+    std::env::temp_dir().join(format!("merkle-{}-{}", std::process::id(), name))
+}
+
+/// Counts what every thread allocates, to check what should not allocate
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+// SAFETY: everything is done by `System`, the count is a thread local without destructor
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// What `f` returns, and how many times it allocated on this thread
+pub(crate) fn allocations<R>(f: impl FnOnce() -> R) -> (R, usize){
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
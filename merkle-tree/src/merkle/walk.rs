@@ -0,0 +1,133 @@
+use std::borrow::Cow;
+
+use crate::hashers::{CryptoHasher, Hashable, digest::HashOutput};
+
+use super::{combiner::NodeCombiner, merkle_trace::MerkleTrace, TreeShape, MerkleError};
+
+/// Trees whose nodes are reached from the root through their children.
+///
+/// `MerkleTree` finds them by position and `StoredMerkleTree` by hash,
+/// both prove and update leaves walking down the same way.
+pub(crate) trait TreeNodes{
+    type Hash: HashOutput;
+    /// What the tree finds a node by
+    type Node;
+    type Error: From<MerkleError>;
+
+    fn root_node(&self) -> Self::Node;
+
+    fn hash_of<'a>(&'a self, node: &'a Self::Node) -> Result<Cow<'a, Self::Hash>, Self::Error>;
+
+    /// Left and right children of an interior node, the right one holds the lower indices
+    fn children(&self, node: &Self::Node) -> Result<(Self::Node, Self::Node), Self::Error>;
+}
+
+/// Interior node whose hash changed in `apply`, along with the hashes of its children
+pub(crate) struct Changed<D>{
+    pub(crate) level: usize,
+    pub(crate) position: usize,
+    pub(crate) hash: D,
+    pub(crate) left: D,
+    pub(crate) right: D,
+}
+
+/// Levels below the root of a tree with `len` leaves
+pub(crate) fn depth(len: usize) -> usize{
+    len.next_power_of_two().trailing_zeros() as usize
+}
+
+/// Trace of the leaf at `which`, walking down from the root
+pub(crate) fn trace<N: TreeNodes>(tree: &N, which: usize, len: usize, shape: TreeShape) -> Result<MerkleTrace, N::Error>{
+    if which >= len {
+        return Err(MerkleError::IndexOutOfBounds { index: which, len }.into());
+    }
+
+    let depth = depth(len);
+    let mut nodes = Vec::with_capacity(depth + 1);
+    let mut siblings = Vec::with_capacity(depth);
+    let mut node = tree.root_node();
+    for level in (0..depth).rev() {
+        let (left, right) = tree.children(&node)?;
+        let (next, sibling) = match (which >> level) & 1 {
+            0 => (right, left),
+            _ => (left, right),
+        };
+        nodes.push(tree.hash_of(&node)?.as_hash().into_owned());
+        siblings.push(tree.hash_of(&sibling)?.as_hash().into_owned());
+        node = next;
+    }
+    nodes.push(tree.hash_of(&node)?.as_hash().into_owned());
+
+    nodes.reverse();
+    siblings.reverse();
+    Ok(MerkleTrace::from_hashes(which, len, shape, nodes, siblings))
+}
+
+/// Hashes of the leaves `updates` replace, sorted by index.
+///
+/// If an index is repeated, the last value wins. Fails if any index is out of bounds.
+pub(crate) fn changed_leaves<H, D, T>(updates: &[(usize, &T)], len: usize, shape: TreeShape) -> Result<Vec<(usize, D)>, MerkleError>
+where H: CryptoHasher, D: HashOutput, T: Hashable + ?Sized
+{
+    if let Some((index, _)) = updates.iter().find(|(index, _)| *index >= len) {
+        return Err(MerkleError::IndexOutOfBounds { index: *index, len })
+    }
+
+    let width = len.next_power_of_two();
+    let mut hashes = Vec::with_capacity(updates.len() * 2);
+    for (index, value) in updates {
+        let hash = D::leaf::<H>(value.to_bits());
+        // The extension repeats the tree from its beginning
+        if shape == TreeShape::FullCopyExtend && index + len < width {
+            hashes.push((index + len, hash.clone()));
+        }
+        hashes.push((*index, hash));
+    }
+
+    hashes.sort_by_key(|(index, _)| *index);
+    hashes.dedup_by(|later, earlier| {
+        if later.0 != earlier.0 {
+            return false;
+        }
+        std::mem::swap(later, earlier);
+        true
+    });
+
+    Ok(hashes)
+}
+
+/// New root of `tree` with `leaves`, from `changed_leaves`, replaced.
+///
+/// Only the ancestors of the leaves are hashed again, once each, and added to `changed`.
+pub(crate) fn apply<H, C, N>(tree: &N, leaves: &[(usize, N::Hash)], len: usize, shape: TreeShape, changed: &mut Vec<Changed<N::Hash>>) -> Result<N::Hash, N::Error>
+where H: CryptoHasher, C: NodeCombiner, N: TreeNodes
+{
+    let root = tree.root_node();
+    apply_under::<H, C, N>(tree, &root, leaves, (depth(len), 0), len, shape, changed)
+}
+
+/// New hash of `node`, at `(level, position)`, with the `leaves` under it replaced
+fn apply_under<H, C, N>(tree: &N, node: &N::Node, leaves: &[(usize, N::Hash)], (level, position): (usize, usize), len: usize, shape: TreeShape, changed: &mut Vec<Changed<N::Hash>>) -> Result<N::Hash, N::Error>
+where H: CryptoHasher, C: NodeCombiner, N: TreeNodes
+{
+    if leaves.is_empty() {
+        return Ok(tree.hash_of(node)?.into_owned());
+    }
+    if level == 0 {
+        return Ok(leaves[0].1.clone());
+    }
+
+    let (old_left, old_right) = tree.children(node)?;
+    let mid = (position * 2 + 1) << (level - 1);
+    let split = leaves.partition_point(|(index, _)| *index < mid);
+    let right = apply_under::<H, C, N>(tree, &old_right, &leaves[..split], (level - 1, position * 2), len, shape, changed)?;
+    let mut left = apply_under::<H, C, N>(tree, &old_left, &leaves[split..], (level - 1, position * 2 + 1), len, shape, changed)?;
+    // The padding of the copy shape is the node next to it
+    if shape == TreeShape::PartialCopyExtend && mid >= len {
+        left = right.clone();
+    }
+
+    let hash = N::Hash::combine::<H, C>(&left, &right);
+    changed.push(Changed { level, position, hash: hash.clone(), left, right });
+    Ok(hash)
+}
@@ -32,10 +32,12 @@ pub(crate) trait Zero {
     fn zero() -> Self;
 }
 
+#[allow(dead_code)]
 pub(crate) trait One {
     fn one() -> Self;
 }
 
+#[allow(dead_code)]
 pub(crate) trait Max{
     fn max() -> Self;
 }
\ No newline at end of file